@@ -0,0 +1,53 @@
+//! bots, for filling empty seats
+
+use crate::game::*;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="snake_case")]
+pub enum BotKind {
+    // plays any legal move
+    Random,
+    // tries to play sort of well
+    Heuristic,
+}
+
+impl BotKind {
+    pub fn all() -> &'static [BotKind] {
+        &[
+            BotKind::Random,
+            BotKind::Heuristic,
+        ]
+    }
+}
+
+pub trait Bot: Send + std::fmt::Debug {
+    // pick an action given the room state as seen by our player, None
+    // if we're waiting on someone else
+    fn act(&mut self, player: &str, state: &GameState) -> Option<GameAction>;
+}
+
+/// plays any legal move, works for any game that knows its legal moves
+#[derive(Debug)]
+pub struct RandomBot {
+    rng: StdRng,
+}
+
+impl RandomBot {
+    pub fn new() -> Self {
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Bot for RandomBot {
+    fn act(&mut self, _player: &str, state: &GameState) -> Option<GameAction> {
+        let actions = state.0["legal_actions"].as_array()?;
+        actions.choose(&mut self.rng)
+            .map(|action| GameAction(action.clone()))
+    }
+}
@@ -0,0 +1,183 @@
+//! errors we can send back to clients
+
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// couldn't make sense of the message
+    BadMessage(String),
+    /// something went wrong on our end
+    Internal(String),
+    /// client asked for a protocol version we don't speak
+    UnsupportedVersion(String),
+
+    // waiting room errors
+    NoRoomName,
+    NoHostName,
+    NoSuchRoom(String),
+    RoomExists(String),
+    WrongPassword(String),
+    InvalidOptions(String),
+    NoSuchGame(String),
+
+    // game room errors
+    NameTaken(String),
+    NotJoined,
+    WrongPlayer(String),
+    Spectating,
+    NoReplay(String),
+    NoSuchBot(String),
+    RoomFull(usize),
+    NotEnoughPlayers(usize),
+    NotHost,
+    Kicked,
+    Banned,
+    RoomLocked,
+
+    // game errors
+    NotPlaying(String),
+    NotYourTurn,
+    InvalidAction(String),
+}
+
+impl Error {
+    /// machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::BadMessage(_)         => "bad_message",
+            Error::Internal(_)           => "internal",
+            Error::UnsupportedVersion(_) => "unsupported_version",
+            Error::NoRoomName            => "no_room_name",
+            Error::NoHostName            => "no_host_name",
+            Error::NoSuchRoom(_)         => "no_such_room",
+            Error::RoomExists(_)         => "room_exists",
+            Error::WrongPassword(_)      => "wrong_password",
+            Error::InvalidOptions(_)     => "invalid_options",
+            Error::NoSuchGame(_)         => "no_such_game",
+            Error::NameTaken(_)          => "name_taken",
+            Error::NotJoined             => "not_joined",
+            Error::WrongPlayer(_)        => "wrong_player",
+            Error::Spectating            => "spectating",
+            Error::NoReplay(_)           => "no_replay",
+            Error::NoSuchBot(_)          => "no_such_bot",
+            Error::RoomFull(_)           => "room_full",
+            Error::NotEnoughPlayers(_)   => "not_enough_players",
+            Error::NotHost               => "not_host",
+            Error::Kicked                => "kicked",
+            Error::Banned                => "banned",
+            Error::RoomLocked            => "room_locked",
+            Error::NotPlaying(_)         => "not_playing",
+            Error::NotYourTurn           => "not_your_turn",
+            Error::InvalidAction(_)      => "invalid_action",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadMessage(err) => write!(f, "bad message: {}", err),
+            Error::Internal(err) => write!(f, "internal error: {}", err),
+            Error::UnsupportedVersion(version) => write!(f,
+                "unsupported protocol version {:?}, we speak {:?}",
+                version, crate::protocol::PROTOCOL_VERSIONS),
+            Error::NoRoomName => write!(f, "can't create room without name"),
+            Error::NoHostName => write!(f, "can't create room without a host"),
+            Error::NoSuchRoom(room) => write!(f, "room does not exist? {:?}", room),
+            Error::RoomExists(room) => write!(f, "room already exists {:?}", room),
+            Error::WrongPassword(room) => write!(f, "wrong password for room {:?}", room),
+            Error::InvalidOptions(err) => write!(f, "invalid options: {}", err),
+            Error::NoSuchGame(id) => write!(f, "no such game {:?}", id),
+            Error::NameTaken(name) => write!(f, "name {:?} is already taken", name),
+            Error::NotJoined => write!(f, "join the game first"),
+            Error::WrongPlayer(name) => write!(f, "can't act as someone else, you are {:?}", name),
+            Error::Spectating => write!(f, "spectators can't play"),
+            Error::NoReplay(room) => write!(f, "no finished game to replay in {:?}", room),
+            Error::NoSuchBot(kind) => write!(f, "no {} bot for this game", kind),
+            Error::RoomFull(max) => write!(f, "room is full, at most {} players", max),
+            Error::NotEnoughPlayers(min) => write!(f, "need at least {} players to start", min),
+            Error::NotHost => write!(f, "only the host can do that"),
+            Error::Kicked => write!(f, "you've been kicked, wait for the next game"),
+            Error::Banned => write!(f, "you've been banned from this room"),
+            Error::RoomLocked => write!(f, "room is locked, no new players"),
+            Error::NotPlaying(name) => write!(f, "player {:?} is not playing?", name),
+            Error::NotYourTurn => write!(f, "not your turn!"),
+            Error::InvalidAction(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::BadMessage(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl From<actix::MailboxError> for Error {
+    fn from(err: actix::MailboxError) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
+impl actix_web::ResponseError for Error {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        match self {
+            Error::NoSuchRoom(_) | Error::NoReplay(_) => StatusCode::NOT_FOUND,
+            Error::WrongPassword(_) => StatusCode::FORBIDDEN,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> actix_web::HttpResponse {
+        actix_web::HttpResponse::build(self.status_code())
+            .json(serde_json::json!({"error": self}))
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Error", 2)?;
+        s.serialize_field("code", self.code())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+/// error reply, only sent to the client that caused the error, echoing
+/// their request_id if they gave us one, and the seq of their message
+#[derive(Debug, Serialize, Clone)]
+pub struct ErrorReply {
+    pub error: Error,
+    pub request_id: Option<serde_json::Value>,
+    pub reply_to: Option<u64>,
+}
+
+impl ErrorReply {
+    pub fn new(error: Error, text: &str) -> Self {
+        // find the request_id and seq, if there are any, the message may
+        // not even be valid json, so this is best-effort
+        let msg = serde_json::from_str::<serde_json::Value>(text).ok();
+        let request_id = msg.as_ref()
+            .and_then(|msg| msg["payload"].get("request_id").cloned());
+        let reply_to = msg.as_ref()
+            .and_then(|msg| msg["seq"].as_u64());
+
+        Self {
+            error,
+            request_id,
+            reply_to,
+        }
+    }
+}
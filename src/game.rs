@@ -2,6 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 use actix::prelude::*;
+use crate::error::*;
 
 #[derive(Debug, Message, Serialize, Deserialize, Clone)]
 #[serde(rename_all="snake_case")]
@@ -13,21 +14,54 @@ pub struct GameState(pub serde_json::Value);
 #[rtype(result="()")]
 pub struct GameAction(pub serde_json::Value);
 
+/// what timeout actions are called, only the server may take these
+pub const TIMEOUT_ACTION: &str = "timeout";
+
 pub trait Game: Send + std::fmt::Debug {
     // extra info for users
     fn status(&self) -> String;
 
-    // get game state, automatically broadcasted on
+    // get game state as seen by a specific player, None gives the
+    // public view for spectators, automatically broadcasted on
     // successful actions
-    fn state(&self) -> GameState;
+    fn state_for(&self, player: Option<&str>) -> GameState;
+
+    // full internal state, used to save and restore games
+    fn save(&self) -> Result<serde_json::Value, Error>;
 
     fn ended(&self) -> bool {
         false
     }
 
+    // who we're waiting on, if anyone, used for turn timers
+    fn current_player(&self) -> Option<String> {
+        None
+    }
+
+    // every action the player could take right now, or None if the
+    // game doesn't know
+    fn legal_actions(&self, _player: &str) -> Option<Vec<GameAction>> {
+        None
+    }
+
+    // what to do when the current player takes too long, games
+    // without a sensible default just wait, the action must be
+    // called TIMEOUT_ACTION so clients can't send it themselves
+    fn timeout_action(&self) -> Option<GameAction> {
+        None
+    }
+
+    // a player left mid-game, each game decides whether to forfeit,
+    // skip or replace them, by default they have to stay until the
+    // game is over
+    fn player_left(&mut self, _player: &str) -> Result<(), Error> {
+        Err(Error::InvalidAction(
+            "can't leave this game until it's over".to_string()))
+    }
+
     // take an action, may error
     fn action(
         &mut self,
         action: GameAction,
-    ) -> Result<(), Box<dyn std::error::Error>>;
+    ) -> Result<(), Error>;
 }
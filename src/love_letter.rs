@@ -0,0 +1,1254 @@
+//! love letter
+
+use crate::game::*;
+use crate::error::*;
+use crate::registry::*;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::iter;
+
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+enum LoveLetterCard {
+    Guard,
+    Priest,
+    Baron,
+    Handmaid,
+    Prince,
+    King,
+    Countess,
+    Princess,
+}
+
+impl LoveLetterCard {
+    fn value(&self) -> u32 {
+        match *self {
+            LoveLetterCard::Guard    => 1,
+            LoveLetterCard::Priest   => 2,
+            LoveLetterCard::Baron    => 3,
+            LoveLetterCard::Handmaid => 4,
+            LoveLetterCard::Prince   => 5,
+            LoveLetterCard::King     => 6,
+            LoveLetterCard::Countess => 7,
+            LoveLetterCard::Princess => 8,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match *self {
+            LoveLetterCard::Guard    => 5,
+            LoveLetterCard::Priest   => 2,
+            LoveLetterCard::Baron    => 2,
+            LoveLetterCard::Handmaid => 2,
+            LoveLetterCard::Prince   => 2,
+            LoveLetterCard::King     => 1,
+            LoveLetterCard::Countess => 1,
+            LoveLetterCard::Princess => 1,
+        }
+    }
+
+    fn all() -> &'static [LoveLetterCard] {
+        &[
+            LoveLetterCard::Guard,
+            LoveLetterCard::Priest,
+            LoveLetterCard::Baron,
+            LoveLetterCard::Handmaid,
+            LoveLetterCard::Prince,
+            LoveLetterCard::King,
+            LoveLetterCard::Countess,
+            LoveLetterCard::Princess,
+        ]
+    }
+
+    // does this card need to target another player?
+    fn targets_other(&self) -> bool {
+        matches!(
+            *self,
+            LoveLetterCard::Guard
+                | LoveLetterCard::Priest
+                | LoveLetterCard::Baron
+                | LoveLetterCard::King
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all="snake_case")]
+enum LoveLetterPhase {
+    BeforeTurn,
+    Turn,
+    RoundOver,
+    Ended,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoveLetterGame {
+    players: Vec<String>,
+    current: usize,
+    phase: LoveLetterPhase,
+    round: usize,
+    // all randomness comes from the seed, so games can be replayed
+    seed: u64,
+
+    // tokens of affection
+    tokens: Vec<usize>,
+    eliminated: Vec<bool>,
+    protected: Vec<bool>,
+    // players who left the game, they sit out every round after
+    #[serde(default)]
+    left: Vec<bool>,
+
+    // down hands are secret, up hands are the cards each player
+    // has played/discarded this round, which are public
+    up_hands: Vec<Vec<LoveLetterCard>>,
+    down_hands: Vec<Vec<LoveLetterCard>>,
+    deck: Vec<LoveLetterCard>,
+    // the face-down card removed at the start of each round
+    removed: Option<LoveLetterCard>,
+    // with two players, three more cards are removed face-up
+    face_up: Vec<LoveLetterCard>,
+
+    // who has looked at whose hand with a priest
+    peeks: Vec<(usize, usize)>,
+
+    log: Vec<String>,
+
+    #[serde(default)]
+    options: LoveLetterOptions,
+}
+
+/// options picked when creating a room
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all="snake_case")]
+pub struct LoveLetterOptions {
+    // otherwise depends on the number of players
+    #[serde(default)]
+    pub tokens_to_win: Option<usize>,
+}
+
+impl LoveLetterOptions {
+    pub fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "tokens_to_win": {
+                    "type": "integer",
+                    "title": "Tokens to win",
+                    "description": "Defaults to 7, 5, 4 or 3 depending on the number of players",
+                    "minimum": 1,
+                    "maximum": 13,
+                },
+            },
+            "additionalProperties": false,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct LoveLetterFactory;
+
+impl GameFactory for LoveLetterFactory {
+    fn id(&self) -> &str {
+        "love_letter"
+    }
+
+    fn name(&self) -> &str {
+        "Love Letter"
+    }
+
+    fn description(&self) -> &str {
+        "Get your letter to the princess, with a little help from the court"
+    }
+
+    fn min_players(&self) -> usize {
+        2
+    }
+
+    fn max_players(&self) -> usize {
+        4
+    }
+
+    fn assets(&self) -> Vec<String> {
+        [
+            "back", "guard", "priest", "baron", "handmaid",
+            "prince", "king", "countess", "princess",
+        ].iter()
+            .map(|card| format!("ll-card-{}.svg", card))
+            .collect()
+    }
+
+    fn options_schema(&self) -> serde_json::Value {
+        LoveLetterOptions::schema()
+    }
+
+    fn create(
+        &self,
+        players: Vec<String>,
+        seed: u64,
+        options: serde_json::Value,
+    ) -> Result<Box<dyn Game>, Error> {
+        Ok(Box::new(LoveLetterGame::new(
+            players,
+            seed,
+            serde_json::from_value(options)?
+        )))
+    }
+
+    fn load(&self, state: serde_json::Value) -> Result<Box<dyn Game>, Error> {
+        Ok(Box::new(LoveLetterGame::load(state)?))
+    }
+}
+
+// this is sort of a stub, we only allow drawing from the main deck
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[serde(rename_all="snake_case")]
+enum LoveLetterDeck {
+    Deck
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag="action", rename_all="snake_case")]
+enum LoveLetterAction {
+    Draw {
+        user: String,
+        deck: LoveLetterDeck,
+    },
+    Play {
+        user: String,
+        card: LoveLetterCard,
+        target: String,
+        // only needed for guards
+        #[serde(default)]
+        guess: Option<LoveLetterCard>,
+    },
+    NextRound {
+        user: String,
+    },
+}
+
+impl LoveLetterGame {
+    pub fn new(
+        mut players: Vec<String>,
+        seed: u64,
+        options: LoveLetterOptions,
+    ) -> LoveLetterGame {
+        // shuffle the player order!
+        players.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let mut game = LoveLetterGame {
+            tokens: vec![0; players.len()],
+            eliminated: vec![],
+            protected: vec![],
+            left: vec![false; players.len()],
+            players,
+            current: 0,
+            phase: LoveLetterPhase::BeforeTurn,
+            round: 0,
+            seed,
+            up_hands: vec![],
+            down_hands: vec![],
+            deck: vec![],
+            removed: None,
+            face_up: vec![],
+            peeks: vec![],
+            log: vec![
+                format!("Waiting for players..."),
+            ],
+            options,
+        };
+
+        game.new_round(0);
+        game
+    }
+
+    /// tokens of affection needed to win the game
+    fn tokens_to_win(&self) -> usize {
+        if let Some(tokens_to_win) = self.options.tokens_to_win {
+            return tokens_to_win;
+        }
+
+        match self.players.len() {
+            0..=2 => 7,
+            3     => 5,
+            4     => 4,
+            _     => 3,
+        }
+    }
+
+    fn new_round(&mut self, first: usize) {
+        self.round += 1;
+        self.log.push("Shuffling...".to_string());
+
+        // build and shuffle the full deck, each round gets its own
+        // rng derived from the seed
+        let mut deck = LoveLetterCard::all().iter()
+            .flat_map(|card| iter::repeat_n(*card, card.count()))
+            .collect::<Vec<_>>();
+        deck.shuffle(&mut StdRng::seed_from_u64(
+            self.seed.wrapping_add(self.round as u64)
+        ));
+
+        // remove one card face-down, and three face-up with two players
+        self.removed = deck.pop();
+        self.face_up = if self.active().len() == 2 {
+            deck.split_off(deck.len() - 3)
+        } else {
+            vec![]
+        };
+        if !self.face_up.is_empty() {
+            self.log.push(format!(
+                "Removed {}",
+                self.face_up.iter()
+                    .map(|card| format!("{:?}", card))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        // give each player one card at the start, anyone who left
+        // is out before the round starts
+        self.down_hands = self.left.iter()
+            .map(|left| {
+                if *left {
+                    vec![]
+                } else {
+                    deck.pop().into_iter().collect::<Vec<_>>()
+                }
+            })
+            .collect::<Vec<_>>();
+        self.up_hands = iter::repeat_n(vec![], self.players.len())
+            .collect::<Vec<_>>();
+        self.eliminated = self.left.clone();
+        self.protected = vec![false; self.players.len()];
+        self.peeks = vec![];
+        self.deck = deck;
+
+        self.current = first;
+        while self.left[self.current] {
+            self.current = (self.current+1) % self.players.len();
+        }
+        self.phase = LoveLetterPhase::BeforeTurn;
+        self.log.push(format!("Round {} started", self.round));
+    }
+
+    pub fn load(state: serde_json::Value) -> Result<LoveLetterGame, Error> {
+        let mut game: LoveLetterGame = serde_json::from_value(state)?;
+        // saved before anyone could leave
+        game.left.resize(game.players.len(), false);
+        Ok(game)
+    }
+
+    fn find_player(
+        &self,
+        user: &str,
+    ) -> Result<usize, Error> {
+        self.players.iter().position(|p| p == user)
+            .ok_or_else(|| Error::NotPlaying(user.to_string()))
+    }
+
+    /// players still in the game
+    fn active(&self) -> Vec<usize> {
+        (0..self.players.len())
+            .filter(|i| !self.left[*i])
+            .collect()
+    }
+
+    /// players still in the round
+    fn remaining(&self) -> Vec<usize> {
+        (0..self.players.len())
+            .filter(|i| !self.eliminated[*i])
+            .collect()
+    }
+
+    fn eliminate(&mut self, player: usize) {
+        // eliminated players reveal their hand
+        let hand = self.down_hands[player].drain(..).collect::<Vec<_>>();
+        self.up_hands[player].extend(hand);
+        self.eliminated[player] = true;
+        self.protected[player] = false;
+        self.log.push(format!("{} is out of the round", self.players[player]));
+    }
+
+    fn end_round(&mut self) {
+        let remaining = self.remaining();
+        let winners = if remaining.len() == 1 {
+            remaining
+        } else {
+            for i in remaining.iter() {
+                self.log.push(format!(
+                    "{} reveals {}",
+                    self.players[*i],
+                    self.down_hands[*i].iter()
+                        .map(|card| format!("{:?}", card))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+
+            // showdown, highest card wins, ties go to whoever
+            // has played the most
+            let score = |i: usize| (
+                self.down_hands[i].iter()
+                    .map(|card| card.value())
+                    .max()
+                    .unwrap_or(0),
+                self.up_hands[i].iter()
+                    .map(|card| card.value())
+                    .sum::<u32>(),
+            );
+
+            let best = remaining.iter()
+                .map(|i| score(*i))
+                .max()
+                .unwrap_or((0, 0));
+            remaining.into_iter()
+                .filter(|i| score(*i) == best)
+                .collect::<Vec<_>>()
+        };
+
+        for winner in winners.iter() {
+            self.tokens[*winner] += 1;
+            self.log.push(format!(
+                "{} wins the round and a token of affection",
+                self.players[*winner]
+            ));
+        }
+
+        // anyone won the game?
+        let tokens_to_win = self.tokens_to_win();
+        let champions = winners.iter()
+            .filter(|i| self.tokens[**i] >= tokens_to_win)
+            .collect::<Vec<_>>();
+        if !champions.is_empty() {
+            for champion in champions {
+                self.log.push(format!("{} wins!", self.players[*champion]));
+            }
+            self.phase = LoveLetterPhase::Ended;
+        } else {
+            // winner of the round goes first next round
+            self.current = winners[0];
+            self.phase = LoveLetterPhase::RoundOver;
+        }
+    }
+
+    fn end_turn(&mut self) {
+        // end of round?
+        if self.remaining().len() <= 1 || self.deck.is_empty() {
+            self.end_round();
+            return;
+        }
+
+        // move on to next player still in the round
+        loop {
+            self.current = (self.current+1) % self.players.len();
+            if !self.eliminated[self.current] {
+                break;
+            }
+        }
+
+        // handmaid only lasts until your next turn
+        self.protected[self.current] = false;
+        self.phase = LoveLetterPhase::BeforeTurn;
+    }
+
+    fn play(
+        &mut self,
+        user: usize,
+        card: LoveLetterCard,
+        target: usize,
+        guess: Option<LoveLetterCard>,
+    ) -> Result<(), Error> {
+        let i = self.down_hands[user].iter().position(|c| *c == card)
+            .ok_or_else(|| Error::InvalidAction(format!(
+                "you don't have a {:?}?", card)))?;
+
+        // you have to get rid of the countess if caught with royalty
+        if
+            card != LoveLetterCard::Countess
+            && self.down_hands[user].contains(&LoveLetterCard::Countess)
+            && self.down_hands[user].iter().any(|c| {
+                *c == LoveLetterCard::King || *c == LoveLetterCard::Prince
+            })
+        {
+            Err(Error::InvalidAction(
+                "you must play the Countess".to_string()))?;
+        }
+
+        // can we even target them?
+        if card.targets_other() || card == LoveLetterCard::Prince {
+            if self.eliminated[target] {
+                Err(Error::InvalidAction(format!(
+                    "{} is out of the round", self.players[target])))?;
+            }
+
+            if target != user && self.protected[target] {
+                Err(Error::InvalidAction(format!(
+                    "{} is protected", self.players[target])))?;
+            }
+
+            let others = self.remaining().into_iter()
+                .filter(|i| *i != user && !self.protected[*i])
+                .count();
+            if card.targets_other() && target == user && others > 0 {
+                Err(Error::InvalidAction(
+                    "you need to target another player".to_string()))?;
+            }
+        }
+
+        let guess = match card {
+            LoveLetterCard::Guard if target != user => {
+                match guess {
+                    Some(LoveLetterCard::Guard) => {
+                        Err(Error::InvalidAction(
+                            "you can't guess Guard".to_string()))?
+                    }
+                    Some(guess) => Some(guess),
+                    None => Err(Error::InvalidAction(
+                        "you need to guess a card".to_string()))?,
+                }
+            }
+            _ => None,
+        };
+
+        // play the card
+        self.down_hands[user].remove(i);
+        self.up_hands[user].push(card);
+        match card.targets_other() {
+            true if target != user => {
+                self.log.push(format!(
+                    "{} played {:?} on {}",
+                    self.players[user],
+                    card,
+                    self.players[target],
+                ));
+            }
+            _ => {
+                self.log.push(format!(
+                    "{} played {:?}",
+                    self.players[user],
+                    card,
+                ));
+            }
+        }
+
+        // no one to target? then the card does nothing
+        if card.targets_other() && target == user {
+            self.log.push("No one to target...".to_string());
+            return Ok(());
+        }
+
+        match card {
+            LoveLetterCard::Guard => {
+                let guess = guess.unwrap();
+                self.log.push(format!(
+                    "{} guesses {:?}",
+                    self.players[user],
+                    guess
+                ));
+                if self.down_hands[target].contains(&guess) {
+                    self.eliminate(target);
+                } else {
+                    self.log.push("Wrong!".to_string());
+                }
+            }
+            LoveLetterCard::Priest => {
+                // only the priest gets to see this
+                self.peeks.push((user, target));
+            }
+            LoveLetterCard::Baron => {
+                let ours = self.down_hands[user].iter()
+                    .map(|c| c.value()).max().unwrap_or(0);
+                let theirs = self.down_hands[target].iter()
+                    .map(|c| c.value()).max().unwrap_or(0);
+                if ours > theirs {
+                    self.eliminate(target);
+                } else if theirs > ours {
+                    self.eliminate(user);
+                } else {
+                    self.log.push("It's a tie".to_string());
+                }
+            }
+            LoveLetterCard::Handmaid => {
+                self.protected[user] = true;
+            }
+            LoveLetterCard::Prince => {
+                let hand = self.down_hands[target].drain(..).collect::<Vec<_>>();
+                for discarded in hand {
+                    self.log.push(format!(
+                        "{} discards {:?}",
+                        self.players[target],
+                        discarded
+                    ));
+                    self.up_hands[target].push(discarded);
+                    if discarded == LoveLetterCard::Princess {
+                        self.eliminate(target);
+                    }
+                }
+
+                // draw a new card, the removed card is used if the
+                // deck runs out
+                if !self.eliminated[target] {
+                    let card = self.deck.pop()
+                        .or_else(|| self.removed.take())
+                        .ok_or_else(|| Error::Internal(
+                            "no cards left to draw?".to_string()))?;
+                    self.down_hands[target].push(card);
+                }
+
+                // whatever anyone peeked at is gone now
+                self.peeks.retain(|(_, peeked)| *peeked != target);
+            }
+            LoveLetterCard::King => {
+                self.down_hands.swap(user, target);
+                // peeks follow the cards
+                for (_, peeked) in self.peeks.iter_mut() {
+                    if *peeked == user {
+                        *peeked = target;
+                    } else if *peeked == target {
+                        *peeked = user;
+                    }
+                }
+            }
+            LoveLetterCard::Countess => {
+                // does nothing, but she's a bit suspicious
+            }
+            LoveLetterCard::Princess => {
+                self.eliminate(user);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Game for LoveLetterGame {
+    fn status(&self) -> String {
+        match self.phase {
+            LoveLetterPhase::Ended => "ended".to_string(),
+            _ => format!("round {}", self.round),
+        }
+    }
+
+    fn save(&self) -> Result<serde_json::Value, Error> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    fn ended(&self) -> bool {
+        matches!(self.phase, LoveLetterPhase::Ended)
+    }
+
+    fn state_for(&self, player: Option<&str>) -> GameState {
+        let viewer = player.and_then(|player| {
+            self.players.iter().position(|p| p == player)
+        });
+        let names = |xs: &Vec<bool>| {
+            xs.iter()
+                .enumerate()
+                .filter(|(_, x)| **x)
+                .map(|(i, _)| &self.players[i])
+                .collect::<Vec<_>>()
+        };
+
+        GameState(serde_json::json!({
+            "players": self.players,
+            "current": self.players[self.current],
+            "phase": self.phase,
+            "round": self.round,
+            "down_hands": self.down_hands.iter()
+                .enumerate()
+                .map(|(i, hand)| {
+                    // down cards are hidden from everyone but their owner
+                    // and anyone who peeked with a priest, at least until
+                    // the round ends
+                    let visible = match self.phase {
+                        LoveLetterPhase::RoundOver => true,
+                        LoveLetterPhase::Ended => true,
+                        _ => viewer.is_some_and(|viewer| {
+                            viewer == i
+                                || self.peeks.contains(&(viewer, i))
+                        }),
+                    };
+
+                    (
+                        &self.players[i],
+                        hand.iter()
+                            .map(|card| if visible { Some(*card) } else { None })
+                            .collect::<Vec<_>>()
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+            "up_hands": self.up_hands.iter()
+                .enumerate()
+                .map(|(i, hand)| {
+                    (&self.players[i], hand)
+                })
+                .collect::<HashMap<_, _>>(),
+            "tokens": self.tokens.iter()
+                .enumerate()
+                .map(|(i, tokens)| {
+                    (&self.players[i], tokens)
+                })
+                .collect::<HashMap<_, _>>(),
+            "tokens_to_win": self.tokens_to_win(),
+            "eliminated": names(&self.eliminated),
+            "left": names(&self.left),
+            "protected": names(&self.protected),
+            "face_up": self.face_up,
+            "decks": [
+                {
+                    "name": "deck",
+                    "card": serde_json::Value::Null,
+                    "count": self.deck.len()
+                },
+            ],
+            "log": self.log,
+            "card_imgs": {
+                "null": "../../ll-card-back.svg",
+                "guard": "../../ll-card-guard.svg",
+                "priest": "../../ll-card-priest.svg",
+                "baron": "../../ll-card-baron.svg",
+                "handmaid": "../../ll-card-handmaid.svg",
+                "prince": "../../ll-card-prince.svg",
+                "king": "../../ll-card-king.svg",
+                "countess": "../../ll-card-countess.svg",
+                "princess": "../../ll-card-princess.svg",
+            }
+        }))
+    }
+
+    fn player_left(&mut self, player: &str) -> Result<(), Error> {
+        let player = self.find_player(player)?;
+        if self.left[player] {
+            Err(Error::NotPlaying(self.players[player].clone()))?;
+        }
+        if self.ended() {
+            return Ok(());
+        }
+
+        self.left[player] = true;
+        self.log.push(format!("{} left the game", self.players[player]));
+
+        // no one left to play against?
+        let active = self.active();
+        if active.len() <= 1 {
+            for winner in active {
+                self.log.push(format!("{} wins!", self.players[winner]));
+            }
+            self.phase = LoveLetterPhase::Ended;
+            return Ok(());
+        }
+
+        // between rounds they just aren't dealt in
+        if let LoveLetterPhase::RoundOver = self.phase {
+            return Ok(());
+        }
+
+        if !self.eliminated[player] {
+            self.eliminate(player);
+        }
+        if player == self.current || self.remaining().len() <= 1 {
+            self.end_turn();
+        }
+        Ok(())
+    }
+
+    fn action(
+        &mut self,
+        action: GameAction,
+    ) -> Result<(), Error> {
+        match (
+            serde_json::from_value(action.0)?,
+            self.phase
+        ) {
+            // draw from the deck
+            (LoveLetterAction::Draw{user, deck: _}, LoveLetterPhase::BeforeTurn) => {
+                let user = self.find_player(&user)?;
+                if user != self.current {
+                    Err(Error::NotYourTurn)?;
+                }
+
+                // draw card
+                let card = self.deck.pop()
+                    .ok_or_else(|| Error::Internal(
+                        "attempted to draw from empty deck?".to_string()))?;
+                self.down_hands[user].push(card);
+
+                // whatever we peeked at last turn has probably moved on
+                self.peeks.retain(|(viewer, _)| *viewer != user);
+
+                // move on to turn
+                self.phase = LoveLetterPhase::Turn;
+                Ok(())
+            }
+            // let the user know they messed up because this happens a lot
+            (LoveLetterAction::Play{user, ..}, LoveLetterPhase::BeforeTurn) => {
+                let user = self.find_player(&user)?;
+                if user != self.current {
+                    Err(Error::NotYourTurn)?;
+                }
+
+                self.log.push(format!(
+                    "{} needs to draw..",
+                    self.players[user]
+                ));
+
+                Ok(())
+            }
+            // play card
+            (
+                LoveLetterAction::Play{user, card, target, guess},
+                LoveLetterPhase::Turn
+            ) => {
+                let user = self.find_player(&user)?;
+                if user != self.current {
+                    Err(Error::NotYourTurn)?;
+                }
+
+                let target = self.find_player(&target)?;
+                self.play(user, card, target, guess)?;
+
+                // move on to next player
+                self.end_turn();
+                Ok(())
+            }
+            // anyone can start the next round
+            (LoveLetterAction::NextRound{user}, LoveLetterPhase::RoundOver) => {
+                self.find_player(&user)?;
+                self.new_round(self.current);
+                Ok(())
+            }
+            _ => {
+                Err(Error::InvalidAction(format!(
+                    "invalid action during phase {:?}",
+                    self.phase
+                )))?
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LoveLetterCard::*;
+
+    fn new_game(n: usize) -> LoveLetterGame {
+        LoveLetterGame::new(
+            (0..n).map(|i| format!("p{}", i)).collect(),
+            0,
+            LoveLetterOptions::default()
+        )
+    }
+
+    fn name(game: &LoveLetterGame, player: usize) -> String {
+        game.players[player].clone()
+    }
+
+    fn act(game: &mut LoveLetterGame, action: serde_json::Value) -> Result<(), Error> {
+        game.action(GameAction(action))
+    }
+
+    fn draw(game: &mut LoveLetterGame, user: usize) -> Result<(), Error> {
+        let user = name(game, user);
+        act(game, serde_json::json!({
+            "action": "draw",
+            "user": user,
+            "deck": "deck",
+        }))
+    }
+
+    fn play(
+        game: &mut LoveLetterGame,
+        user: usize,
+        card: LoveLetterCard,
+        target: usize,
+        guess: Option<LoveLetterCard>,
+    ) -> Result<(), Error> {
+        let user = name(game, user);
+        let target = name(game, target);
+        act(game, serde_json::json!({
+            "action": "play",
+            "user": user,
+            "card": card,
+            "target": target,
+            "guess": guess,
+        }))
+    }
+
+    // every card that isn't in the deck or someone's hand
+    fn count(game: &LoveLetterGame, card: LoveLetterCard) -> usize {
+        game.deck.iter()
+            .chain(game.down_hands.iter().flatten())
+            .chain(game.up_hands.iter().flatten())
+            .chain(game.removed.iter())
+            .chain(game.face_up.iter())
+            .filter(|c| **c == card)
+            .count()
+    }
+
+    // set up hands explicitly, everything else that was in play goes
+    // in the deck, the top of the deck is last, and one of those is
+    // removed again
+    fn rig(game: &mut LoveLetterGame, hands: Vec<Vec<LoveLetterCard>>) {
+        let mut cards = game.deck.drain(..)
+            .chain(game.down_hands.drain(..).flatten())
+            .chain(game.removed.take())
+            .collect::<Vec<_>>();
+        for hand in hands.iter() {
+            for card in hand.iter() {
+                let i = cards.iter().position(|c| c == card).unwrap();
+                cards.remove(i);
+            }
+        }
+        game.down_hands = hands;
+        game.removed = cards.pop();
+        game.deck = cards;
+    }
+
+    // rig hands for player 0's turn, after they've drawn
+    fn rig_turn(game: &mut LoveLetterGame, hands: Vec<Vec<LoveLetterCard>>) {
+        rig(game, hands);
+        game.current = 0;
+        game.phase = LoveLetterPhase::Turn;
+    }
+
+    #[test]
+    fn deck_has_16_cards() {
+        for n in 2..=4 {
+            let game = new_game(n);
+            for card in LoveLetterCard::all() {
+                assert_eq!(count(&game, *card), card.count());
+            }
+            assert!(game.removed.is_some());
+            assert!(game.down_hands.iter().all(|hand| hand.len() == 1));
+
+            // three more are removed face-up with two players
+            if n == 2 {
+                assert_eq!(game.face_up.len(), 3);
+                assert_eq!(game.deck.len(), 16 - 1 - 3 - 2);
+            } else {
+                assert_eq!(game.face_up.len(), 0);
+                assert_eq!(game.deck.len(), 16 - 1 - n);
+            }
+        }
+    }
+
+    #[test]
+    fn countess_is_forced_with_royalty() {
+        for royalty in [King, Prince].iter() {
+            let mut game = new_game(3);
+            rig_turn(&mut game, vec![vec![Countess, *royalty], vec![Guard], vec![Guard]]);
+            assert!(matches!(
+                play(&mut game, 0, *royalty, 1, None),
+                Err(Error::InvalidAction(_))
+            ));
+            play(&mut game, 0, Countess, 0, None).unwrap();
+            assert_eq!(game.down_hands[0], vec![*royalty]);
+        }
+
+        // anything else is fine
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Countess, Priest], vec![Guard], vec![Guard]]);
+        play(&mut game, 0, Priest, 1, None).unwrap();
+        assert_eq!(game.down_hands[0], vec![Countess]);
+    }
+
+    #[test]
+    fn baron_compares_hands() {
+        // win
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Baron, Prince], vec![Guard], vec![Guard]]);
+        play(&mut game, 0, Baron, 1, None).unwrap();
+        assert_eq!(game.eliminated, vec![false, true, false]);
+        assert_eq!(game.up_hands[1], vec![Guard]);
+
+        // loss
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Baron, Guard], vec![King], vec![Guard]]);
+        play(&mut game, 0, Baron, 1, None).unwrap();
+        assert_eq!(game.eliminated, vec![true, false, false]);
+        assert_eq!(game.up_hands[0], vec![Baron, Guard]);
+
+        // tie
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Baron, Priest], vec![Priest], vec![Guard]]);
+        play(&mut game, 0, Baron, 1, None).unwrap();
+        assert_eq!(game.eliminated, vec![false, false, false]);
+        assert!(game.log.contains(&"It's a tie".to_string()));
+    }
+
+    #[test]
+    fn prince_discarding_the_princess() {
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Prince, Guard], vec![Princess], vec![Guard]]);
+        let deck = game.deck.len();
+        play(&mut game, 0, Prince, 1, None).unwrap();
+        assert!(game.eliminated[1]);
+        assert_eq!(game.up_hands[1], vec![Princess]);
+        assert!(game.down_hands[1].is_empty());
+        // and no new card for them
+        assert_eq!(game.deck.len(), deck);
+    }
+
+    #[test]
+    fn prince_draws_the_removed_card() {
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Prince, Guard], vec![Priest], vec![Guard]]);
+        game.deck.clear();
+        game.removed = Some(Handmaid);
+
+        play(&mut game, 0, Prince, 1, None).unwrap();
+        assert_eq!(game.up_hands[1], vec![Priest]);
+        assert_eq!(game.down_hands[1], vec![Handmaid]);
+        assert_eq!(game.removed, None);
+        // and that's the end of the round
+        assert!(matches!(game.phase, LoveLetterPhase::RoundOver));
+    }
+
+    #[test]
+    fn handmaid_protects() {
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Guard, Prince], vec![Priest], vec![Baron]]);
+        game.protected[1] = true;
+        assert!(matches!(
+            play(&mut game, 0, Guard, 1, Some(Priest)),
+            Err(Error::InvalidAction(_))
+        ));
+        assert!(matches!(
+            play(&mut game, 0, Prince, 1, None),
+            Err(Error::InvalidAction(_))
+        ));
+        // someone else is still fair game, so we can't target ourself
+        assert!(matches!(
+            play(&mut game, 0, Guard, 0, None),
+            Err(Error::InvalidAction(_))
+        ));
+        play(&mut game, 0, Guard, 2, Some(Baron)).unwrap();
+        assert!(game.eliminated[2]);
+    }
+
+    #[test]
+    fn everyone_protected_targets_self() {
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![Guard, Baron], vec![Priest], vec![Baron]]);
+        game.protected[1] = true;
+        game.protected[2] = true;
+
+        // the card does nothing, no guess needed
+        play(&mut game, 0, Guard, 0, None).unwrap();
+        assert!(game.log.contains(&"No one to target...".to_string()));
+        assert_eq!(game.eliminated, vec![false, false, false]);
+        assert_eq!(game.down_hands[0], vec![Baron]);
+    }
+
+    #[test]
+    fn king_moves_peeks() {
+        let mut game = new_game(3);
+        rig_turn(&mut game, vec![vec![King, Guard], vec![Priest], vec![Baron]]);
+        // player 2 knows player 0 has a guard
+        game.peeks = vec![(2, 0)];
+
+        play(&mut game, 0, King, 1, None).unwrap();
+        assert_eq!(game.down_hands[0], vec![Priest]);
+        assert_eq!(game.down_hands[1], vec![Guard]);
+        // and now knows player 1 has it
+        assert_eq!(game.peeks, vec![(2, 1)]);
+    }
+
+    #[test]
+    fn tokens_to_win() {
+        assert_eq!(new_game(2).tokens_to_win(), 7);
+        assert_eq!(new_game(3).tokens_to_win(), 5);
+        assert_eq!(new_game(4).tokens_to_win(), 4);
+
+        let game = LoveLetterGame::new(
+            vec![format!("p0"), format!("p1")],
+            0,
+            LoveLetterOptions { tokens_to_win: Some(1) }
+        );
+        assert_eq!(game.tokens_to_win(), 1);
+    }
+
+    #[test]
+    fn showdown() {
+        // highest card wins, ties go to whoever played the most
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Prince], vec![Prince], vec![Guard]]);
+        game.up_hands = vec![vec![Guard], vec![Baron], vec![Countess]];
+        game.end_round();
+        assert_eq!(game.tokens, vec![0, 1, 0]);
+        assert_eq!(game.current, 1);
+        assert!(matches!(game.phase, LoveLetterPhase::RoundOver));
+
+        // still tied? then everyone tied wins
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Prince], vec![Prince], vec![Guard]]);
+        game.up_hands = vec![vec![Baron], vec![Baron], vec![]];
+        game.end_round();
+        assert_eq!(game.tokens, vec![1, 1, 0]);
+
+        // enough tokens ends the game
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Prince], vec![Princess], vec![Guard]]);
+        game.tokens = vec![0, 4, 4];
+        game.end_round();
+        assert!(game.ended());
+        let p1 = name(&game, 1);
+        assert!(game.log.contains(&format!("{} wins!", p1)));
+    }
+
+    #[test]
+    fn hands_are_hidden() {
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Guard], vec![Priest], vec![Baron]]);
+        // player 1 peeked at player 2
+        game.peeks = vec![(1, 2)];
+        let (p0, p1, p2) = (name(&game, 0), name(&game, 1), name(&game, 2));
+
+        let hands = |game: &LoveLetterGame, player: Option<&str>| {
+            let state = game.state_for(player).0;
+            vec![
+                state["down_hands"][&p0].clone(),
+                state["down_hands"][&p1].clone(),
+                state["down_hands"][&p2].clone(),
+            ]
+        };
+        let hidden = serde_json::json!([null]);
+
+        assert_eq!(hands(&game, Some(&p0)), vec![
+            serde_json::json!(["guard"]), hidden.clone(), hidden.clone()
+        ]);
+        assert_eq!(hands(&game, Some(&p1)), vec![
+            hidden.clone(), serde_json::json!(["priest"]), serde_json::json!(["baron"])
+        ]);
+        assert_eq!(hands(&game, None), vec![
+            hidden.clone(), hidden.clone(), hidden.clone()
+        ]);
+
+        // everything comes out at the end of the round
+        game.phase = LoveLetterPhase::RoundOver;
+        assert_eq!(hands(&game, None), vec![
+            serde_json::json!(["guard"]),
+            serde_json::json!(["priest"]),
+            serde_json::json!(["baron"]),
+        ]);
+    }
+
+    #[test]
+    fn leaving_passes_the_turn() {
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Guard], vec![Priest], vec![Baron]]);
+        draw(&mut game, 0).unwrap();
+        let drawn = game.down_hands[0][1];
+
+        let p0 = name(&game, 0);
+        game.player_left(&p0).unwrap();
+        assert!(game.left[0]);
+        assert!(game.eliminated[0]);
+        // their hand is revealed
+        assert!(game.down_hands[0].is_empty());
+        assert_eq!(game.up_hands[0], vec![Guard, drawn]);
+        // and it's the next player's turn
+        assert_eq!(game.current, 1);
+        assert!(matches!(game.phase, LoveLetterPhase::BeforeTurn));
+        assert!(game.log.contains(&format!("{} left the game", p0)));
+
+        // they're gone for good
+        assert!(matches!(
+            game.player_left(&p0),
+            Err(Error::NotPlaying(_))
+        ));
+    }
+
+    #[test]
+    fn leaving_out_of_turn() {
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Guard], vec![Priest], vec![Baron]]);
+
+        let p2 = name(&game, 2);
+        game.player_left(&p2).unwrap();
+        assert!(game.eliminated[2]);
+        assert_eq!(game.up_hands[2], vec![Baron]);
+        assert_eq!(game.current, 0);
+        assert!(matches!(game.phase, LoveLetterPhase::BeforeTurn));
+
+        // the turn skips them
+        draw(&mut game, 0).unwrap();
+        game.end_turn();
+        assert_eq!(game.current, 1);
+        game.end_turn();
+        assert_eq!(game.current, 0);
+    }
+
+    #[test]
+    fn leaving_ends_the_round() {
+        let mut game = new_game(3);
+        rig(&mut game, vec![vec![Guard], vec![Priest], vec![Baron]]);
+        game.eliminate(1);
+
+        // only player 0 is left in the round
+        let p2 = name(&game, 2);
+        game.player_left(&p2).unwrap();
+        assert!(matches!(game.phase, LoveLetterPhase::RoundOver));
+        assert_eq!(game.tokens, vec![1, 0, 0]);
+        assert_eq!(game.current, 0);
+    }
+
+    #[test]
+    fn left_players_sit_out_later_rounds() {
+        let mut game = new_game(4);
+        game.current = 2;
+        game.phase = LoveLetterPhase::RoundOver;
+
+        // the player who would have started next
+        let p2 = name(&game, 2);
+        game.player_left(&p2).unwrap();
+        assert!(matches!(game.phase, LoveLetterPhase::RoundOver));
+
+        let p0 = name(&game, 0);
+        act(&mut game, serde_json::json!({
+            "action": "next_round",
+            "user": p0,
+        })).unwrap();
+        assert_eq!(game.current, 3);
+        assert!(game.eliminated[2]);
+        assert!(game.down_hands[2].is_empty());
+        assert_eq!(game.remaining(), vec![0, 1, 3]);
+        // 16 cards, 1 removed, 3 dealt
+        assert_eq!(game.deck.len(), 12);
+    }
+
+    #[test]
+    fn last_player_standing_wins() {
+        let mut game = new_game(3);
+        let p0 = name(&game, 0);
+        let p1 = name(&game, 1);
+        let p2 = name(&game, 2);
+        game.player_left(&p0).unwrap();
+        assert!(!game.ended());
+        game.player_left(&p2).unwrap();
+        assert!(game.ended());
+        assert!(game.log.contains(&format!("{} wins!", p1)));
+
+        // nothing more happens once it's over
+        game.player_left(&p1).unwrap();
+        assert_eq!(game.log.last(), Some(&format!("{} wins!", p1)));
+    }
+
+    #[test]
+    fn two_players_left_get_face_up_cards() {
+        let mut game = new_game(3);
+        assert!(game.face_up.is_empty());
+
+        game.phase = LoveLetterPhase::RoundOver;
+        let p0 = name(&game, 0);
+        game.player_left(&p0).unwrap();
+        game.new_round(game.current);
+        assert_eq!(game.face_up.len(), 3);
+        assert_eq!(game.deck.len(), 10);
+    }
+}
@@ -1,5 +1,4 @@
-
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use actix::prelude::*;
 use actix_files as fs;
@@ -9,44 +8,40 @@ use structopt::StructOpt;
 use log::*;
 use std::num::ParseIntError;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use std::sync::Mutex;
 use std::sync::Arc;
+use std::path::PathBuf;
 use lazy_static::lazy_static;
 use rand::Rng;
 
 // game definitions in other files
+mod error;
+use crate::error::*;
 mod game;
 use crate::game::*;
 mod test_game;
-use crate::test_game::*;
-
-//// game types ////
-
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
-#[serde(rename_all="snake_case")]
-enum GameType {
-    TestGame,
-    OtherTestGame,
-}
-
-impl GameType {
-    fn names() -> Vec<&'static str> {
-        vec!["test_game", "other_test_game"]
-    }
-
-    fn create(&self, players: Vec<String>) -> Box<dyn Game> {
-        match *self {
-            GameType::TestGame      => Box::new(TestGame::new(players)),
-            GameType::OtherTestGame => Box::new(TestGame::new(players)),
-        }
-    }
-}
-
-
-//// random colors ////
-const RANDOM_COLORS: &'static [&'static str] = &[
+mod love_letter;
+mod store;
+use crate::store::*;
+mod protocol;
+use crate::protocol::*;
+mod options;
+mod bot;
+use crate::bot::*;
+mod registry;
+use crate::registry::*;
+mod wasm_game;
+mod password;
+use crate::password::*;
+mod sync;
+use crate::sync::*;
+
+
+// -- random colors --
+const RANDOM_COLORS: &[&str] = &[
     "#4c72b0", 
     "#dd8452", 
     "#55a868", 
@@ -75,87 +70,709 @@ fn random_color() -> String {
 }
 
 
-//// game room management ////
+// -- game replays --
+
+/// an accepted game action, who took it, and when
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all="snake_case")]
+struct ReplayStep {
+    // milliseconds since the unix epoch
+    time: u64,
+    player: String,
+    // no action means the player left
+    #[serde(default)]
+    action: Option<GameAction>,
+}
 
+/// everything needed to play back a game exactly
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all="snake_case")]
+struct Replay {
+    #[serde(rename="type")]
+    type_: GameType,
+    players: Vec<String>,
+    seed: u64,
+    #[serde(default)]
+    options: serde_json::Value,
+    steps: Vec<ReplayStep>,
+}
+
+impl Replay {
+    fn new(
+        type_: GameType,
+        players: Vec<String>,
+        seed: u64,
+        options: serde_json::Value,
+    ) -> Self {
+        Self {
+            type_,
+            players,
+            seed,
+            options,
+            steps: vec![],
+        }
+    }
+
+    /// did this player start the game, and are they still in it?
+    fn in_game(&self, player: &str) -> bool {
+        self.players.iter().any(|other| other == player)
+            && !self.steps.iter().any(|step| {
+                step.player == player && step.action.is_none()
+            })
+    }
+
+    fn record(&mut self, player: &str, action: Option<GameAction>) {
+        self.steps.push(ReplayStep {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as u64)
+                .unwrap_or(0),
+            player: player.to_string(),
+            action,
+        });
+    }
+
+    /// play back the game, returning every intermediate state as seen
+    /// by the given player
+    fn states(&self, player: Option<&str>) -> Result<Vec<GameState>, Error> {
+        let mut game = self.type_.create(
+            self.players.clone(),
+            self.seed,
+            &self.options
+        )?;
+        let mut states = vec![game.state_for(player)];
+        for step in self.steps.iter() {
+            match &step.action {
+                Some(action) => game.action(action.clone())?,
+                None => game.player_left(&step.player)?,
+            }
+            states.push(game.state_for(player));
+        }
+
+        Ok(states)
+    }
+}
+
+
+// -- game room management --
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all="snake_case")]
 struct GameRoomState {
     #[serde(rename="type")]
     type_: GameType,
     players: Vec<String>,
+    spectators: usize,
     status: String,
+    // how many players can play
+    min_players: usize,
+    max_players: usize,
+    // needs a password to get in?
+    password_required: bool,
+    // not taking new players?
+    locked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag="action", rename_all="snake_case")]
 enum GameRoomAction {
     JoinGame {
-        name: String
+        name: String,
+        // needed to rejoin as an existing player
+        #[serde(default)]
+        token: Option<String>,
     },
+    Spectate {
+        name: String,
+    },
+    LeaveGame,
     StartGame,
+    AddBot {
+        kind: BotKind,
+        // otherwise we pick one
+        #[serde(default)]
+        name: Option<String>,
+    },
+
+    // host only
+    Kick {
+        name: String,
+    },
+    Ban {
+        name: String,
+        // also ban wherever they're connecting from
+        #[serde(default)]
+        address: bool,
+    },
+    TransferHost {
+        name: String,
+    },
+    LockRoom {
+        locked: bool,
+    },
+}
+
+/// everything we need to bring a room back after a restart
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all="snake_case")]
+struct GameRoomSnapshot {
+    #[serde(rename="type")]
+    type_: GameType,
+    #[serde(default)]
+    options: serde_json::Value,
+    game: Option<serde_json::Value>,
+    players: Vec<String>,
+    player_colors: HashMap<String, String>,
+    player_tokens: HashMap<String, String>,
+    #[serde(default)]
+    replay: Option<Replay>,
+    // in seconds
+    #[serde(default)]
+    turn_limit: Option<u64>,
+    #[serde(default)]
+    bots: HashMap<String, BotKind>,
+    #[serde(default)]
+    password_hash: Option<PasswordHash>,
+    // rooms saved before we hashed passwords, never written
+    #[serde(default, skip_serializing)]
+    password: Option<String>,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    kicked: HashSet<String>,
+    #[serde(default)]
+    banned_names: HashSet<String>,
+    #[serde(default)]
+    banned_addrs: HashSet<String>,
 }
 
 #[derive(Debug)]
 struct GameRoom {
+    name: String,
     type_: GameType,
+    // picked when the room was created, used for every game
+    options: serde_json::Value,
     game: Option<Box<dyn Game>>,
+    // every accepted action in the current game
+    replay: Option<Replay>,
 
     players: Vec<String>,
     player_colors: HashMap<String, String>,
+    // session tokens, handed out on join, proves a client is a player
+    player_tokens: HashMap<String, String>,
 
     // may not == players?
     clients: HashMap<Uuid, Recipient<GameState>>,
+    // which player each client is, clients that haven't joined
+    // only get the public view
+    client_players: HashMap<Uuid, String>,
+    // spectators get the public view, but can't play
+    spectators: HashMap<Uuid, String>,
+    // where each client is connecting from, bots aren't anywhere
+    client_addrs: HashMap<Uuid, String>,
+
+    // where to save snapshots, if anywhere
+    store: Option<Arc<dyn Store>>,
+    // seed to use for every game, from --seed, otherwise every game
+    // gets a random one
+    seed: Option<u64>,
+
+    // how long players get for each turn, if there's a limit
+    turn_limit: Option<Duration>,
+    // whose turn it is, and when they run out of time
+    turn: Option<(String, Instant)>,
+
+    // players that are bots, their clients live in clients like
+    // everyone else
+    bots: HashMap<String, BotKind>,
+
+    // needed to get in, if set
+    password: Option<PasswordHash>,
+    // left out of the listing, only reachable by name
+    private: bool,
+
+    // whoever created the room runs it
+    host: Option<String>,
+    // how long the host can be gone before someone else takes over,
+    // if ever
+    host_grace_period: Option<Duration>,
+    host_away_since: Option<Instant>,
+    // locked rooms don't take new players
+    locked: bool,
+    // kicked players sit out until the next game starts, banning is
+    // the only way to keep someone out for good
+    kicked: HashSet<String>,
+    banned_names: HashSet<String>,
+    banned_addrs: HashSet<String>,
+
+    // keeps track of us for the listing
+    waiting_room: Addr<WaitingRoom>,
 }
 
 impl GameRoom {
-    fn new(type_: GameType) -> Self {
+    fn new(
+        name: &str,
+        type_: GameType,
+        store: Option<Arc<dyn Store>>,
+        waiting_room: Addr<WaitingRoom>,
+    ) -> Self {
         Self {
+            name: name.to_string(),
             game: None,
-            type_: type_,
+            type_,
+            options: serde_json::Value::Null,
+            replay: None,
             players: Vec::new(),
             player_colors: HashMap::new(),
+            player_tokens: HashMap::new(),
             clients: HashMap::new(),
+            client_players: HashMap::new(),
+            spectators: HashMap::new(),
+            client_addrs: HashMap::new(),
+            store,
+            seed: None,
+            turn_limit: None,
+            turn: None,
+            bots: HashMap::new(),
+            password: None,
+            private: false,
+            host: None,
+            host_grace_period: None,
+            host_away_since: None,
+            locked: false,
+            kicked: HashSet::new(),
+            banned_names: HashSet::new(),
+            banned_addrs: HashSet::new(),
+            waiting_room,
+        }
+    }
+
+    fn snapshot(&self) -> Result<GameRoomSnapshot, Error> {
+        Ok(GameRoomSnapshot {
+            type_: self.type_.clone(),
+            options: self.options.clone(),
+            game: self.game.as_ref().map(|game| game.save()).transpose()?,
+            players: self.players.clone(),
+            player_colors: self.player_colors.clone(),
+            player_tokens: self.player_tokens.clone(),
+            replay: self.replay.clone(),
+            turn_limit: self.turn_limit.map(|limit| limit.as_secs()),
+            bots: self.bots.clone(),
+            password_hash: self.password.clone(),
+            password: None,
+            private: self.private,
+            host: self.host.clone(),
+            locked: self.locked,
+            kicked: self.kicked.clone(),
+            banned_names: self.banned_names.clone(),
+            banned_addrs: self.banned_addrs.clone(),
+        })
+    }
+
+    fn restore(
+        name: &str,
+        snapshot: GameRoomSnapshot,
+        store: Option<Arc<dyn Store>>,
+        waiting_room: Addr<WaitingRoom>,
+    ) -> Result<Self, Error> {
+        let mut room = GameRoom::new(name, snapshot.type_, store, waiting_room);
+        room.options = snapshot.options;
+        room.game = snapshot.game
+            .map(|game| room.type_.load(game))
+            .transpose()?;
+        room.players = snapshot.players;
+        room.player_colors = snapshot.player_colors;
+        room.player_tokens = snapshot.player_tokens;
+        room.replay = snapshot.replay;
+        room.turn_limit = snapshot.turn_limit.map(Duration::from_secs);
+        // bots come back once we're started
+        room.bots = snapshot.bots;
+        let password = snapshot.password;
+        room.password = snapshot.password_hash.or_else(|| {
+            password.map(|password| PasswordHash::new(&password))
+        });
+        room.private = snapshot.private;
+        room.host = snapshot.host;
+        room.locked = snapshot.locked;
+        room.kicked = snapshot.kicked;
+        room.banned_names = snapshot.banned_names;
+        room.banned_addrs = snapshot.banned_addrs;
+        // everyone gets a fresh turn after a restart
+        room.update_turn(true);
+        Ok(room)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        if let Some(store) = &self.store {
+            store.save(
+                &self.name,
+                &serde_json::to_value(self.snapshot()?)?
+            )?;
         }
+        Ok(())
     }
 
 
     /// this is the status of the room for the waiting room
-    fn room_state(&self) -> serde_json::Value {
-        serde_json::json!({
-            "type": self.type_,
-            "players": self.players,
-            "status": match &self.game {
+    fn room_state(&self) -> GameRoomState {
+        GameRoomState {
+            type_: self.type_.clone(),
+            players: self.players.clone(),
+            spectators: self.spectators.len(),
+            status: match &self.game {
                 Some(game) => game.status(),
-                None => format!("waiting on players..."),
-            }
-        })
+                None => "waiting on players...".to_string(),
+            },
+            min_players: self.type_.min_players(),
+            max_players: self.type_.max_players(),
+            password_required: self.password.is_some(),
+            locked: self.locked,
+        }
+    }
+
+    /// let the waiting room know how we're doing
+    fn notify_waiting_room(&self) {
+        self.waiting_room.do_send(GameRoomUpdate {
+            room_name: self.name.clone(),
+            state: self.room_state(),
+            empty: !self.has_humans(),
+        });
     }
 
     fn broadcast_state(&self) {
-        // broadcast update
-        let state = self.state();
-        for (_, client) in self.clients.iter() {
-            client.do_send(state.clone()).warn_err().ok();
+        // broadcast update, each client only gets what their
+        // player is allowed to see
+        for (uuid, client) in self.clients.iter() {
+            let player = self.client_players.get(uuid).map(|p| p.as_str());
+            client.do_send(self.state_for(player)).warn_err().ok();
         }
     }
 
-    /// game state
-    fn state(&self) -> GameState {
+    /// game state as seen by a specific player, or None for spectators
+    fn state_for(&self, player: Option<&str>) -> GameState {
+        let mut spectators = self.spectators.values().collect::<Vec<_>>();
+        spectators.sort();
+        let mut bots = self.bots.keys().collect::<Vec<_>>();
+        bots.sort();
+        let mut banned = self.banned_names.iter().collect::<Vec<_>>();
+        banned.sort();
+
         // inject player info
         GameState(serde_json::json!({
             "game": self.game.as_ref().map(|game| {
-                game.state()
+                game.state_for(player)
             }),
             "players": self.players,
             "player_colors": self.player_colors,
+            "spectators": spectators,
+            "bots": bots,
+            // what add_bot can add, empty if the game has no bots
+            "bot_kinds": self.type_.bot_kinds(),
+            "host": self.host,
+            "locked": self.locked,
+            "banned": banned,
+            // once the game is over, anyone can reproduce it, note this
+            // is a string since javascript can't handle 64-bit ints
+            "seed": match (&self.game, &self.replay) {
+                (Some(game), Some(replay)) if game.ended() => {
+                    Some(replay.seed.to_string())
+                }
+                _ => None,
+            },
+            // so clients can show a countdown, in seconds
+            "turn_limit": self.turn_limit.map(|limit| limit.as_secs()),
+            "turn_time_left": self.turn.as_ref().map(|(_, deadline)| {
+                deadline.saturating_duration_since(Instant::now())
+                    .as_secs_f64()
+            }),
+            // what we can do right now, if the game knows
+            "legal_actions": match (&self.game, player) {
+                (Some(game), Some(player)) => game.legal_actions(player),
+                _ => None,
+            },
+            // only our own token, so we can rejoin later
+            "token": player.and_then(|player| {
+                self.player_tokens.get(player)
+            }),
         }))
     }
 
-    /// game actions
-    fn action(
+    /// is there a seat for one more player?
+    fn check_room_for_player(&self) -> Result<(), Error> {
+        if self.players.len() >= self.type_.max_players() {
+            Err(Error::RoomFull(self.type_.max_players()))?;
+        }
+        Ok(())
+    }
+
+    /// only the host can do this
+    fn check_host(&self, client: Uuid) -> Result<(), Error> {
+        if self.spectators.contains_key(&client) {
+            Err(Error::Spectating)?;
+        }
+
+        let player = self.client_players.get(&client)
+            .ok_or(Error::NotJoined)?;
+        if self.host.as_ref() != Some(player) {
+            Err(Error::NotHost)?;
+        }
+        Ok(())
+    }
+
+    /// kick a player out of the room, they can't rejoin until the
+    /// next game starts
+    fn kick(&mut self, name: &str) -> Result<(), Error> {
+        if self.host.as_deref() == Some(name) {
+            Err(Error::InvalidAction("can't kick the host".to_string()))?;
+        }
+
+        info!("kicking {:?} from room {:?}", name, self.name);
+        self.remove_player(name)?;
+        self.kicked.insert(name.to_string());
+        Ok(())
+    }
+
+    /// add a new player, returning the session token that proves
+    /// who they are
+    fn add_player(&mut self, name: &str) -> String {
+        let token = Uuid::new_v4().to_string();
+        self.players.push(name.to_string());
+        self.player_colors.insert(name.to_string(), random_color());
+        self.player_tokens.insert(name.to_string(), token.clone());
+        token
+    }
+
+    /// take a player out of the room, if a game is going the game
+    /// decides what happens to their seat, their clients stay
+    /// connected but only get the public view
+    fn remove_player(&mut self, name: &str) -> Result<(), Error> {
+        if !self.players.iter().any(|player| player == name) {
+            Err(Error::NotPlaying(name.to_string()))?;
+        }
+
+        // people who joined after the game started aren't in it, so
+        // there's nothing for the game to do
+        let in_game = self.replay.as_ref()
+            .map(|replay| replay.in_game(name))
+            .unwrap_or(true);
+        if let Some(game) = &mut self.game {
+            if in_game && !game.ended() {
+                game.player_left(name)?;
+                if let Some(replay) = &mut self.replay {
+                    replay.record(name, None);
+                }
+                self.update_turn(false);
+            }
+        }
+
+        self.players.retain(|player| player != name);
+        self.player_colors.remove(name);
+        self.player_tokens.remove(name);
+
+        // bots go away once no one holds on to them
+        let uuids = self.client_players.iter()
+            .filter(|(_, player)| *player == name)
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+        for uuid in uuids {
+            self.client_players.remove(&uuid);
+            if self.bots.contains_key(name) {
+                self.clients.remove(&uuid);
+            }
+        }
+        self.bots.remove(name);
+
+        // someone else runs things now
+        if self.host.as_deref() == Some(name) {
+            self.host = self.players.iter()
+                .find(|player| !self.bots.contains_key(*player))
+                .cloned();
+        }
+        Ok(())
+    }
+
+    /// hand the room over to someone else if the host has been gone
+    /// too long
+    fn check_host_away(&mut self) {
+        let grace_period = match self.host_grace_period {
+            Some(grace_period) => grace_period,
+            None => return,
+        };
+        let client_players = &self.client_players;
+        let connected = |name: &String| {
+            client_players.values().any(|player| player == name)
+        };
+
+        let host = match &self.host {
+            Some(host) if !connected(host) => host.clone(),
+            _ => {
+                self.host_away_since = None;
+                return;
+            }
+        };
+        let away_since = *self.host_away_since
+            .get_or_insert_with(Instant::now);
+        if away_since.elapsed() < grace_period {
+            return;
+        }
+
+        let new_host = self.players.iter()
+            .find(|player| !self.bots.contains_key(*player) && connected(player))
+            .cloned();
+        if let Some(new_host) = new_host {
+            info!("host {:?} of room {:?} is away, {:?} is host now",
+                host, self.name, new_host);
+            self.host = Some(new_host);
+            self.host_away_since = None;
+            self.save().warn_err().ok();
+            self.broadcast_state();
+        }
+    }
+
+    /// are any real people still here?
+    fn has_humans(&self) -> bool {
+        self.client_players.iter()
+            .filter(|(_, player)| self.bots.contains_key(*player))
+            .count() < self.clients.len()
+    }
+
+    /// give a bot a client, so it can see and play
+    fn spawn_bot(
+        &mut self,
+        name: &str,
+        kind: BotKind,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        let bot = self.type_.bot(kind)?;
+        let uuid = Uuid::new_v4();
+        let addr = BotClient::new(uuid, name, bot, ctx.address()).start();
+        self.clients.insert(uuid, addr.recipient());
+        self.client_players.insert(uuid, name.to_string());
+        Ok(())
+    }
+
+    /// pass an action on to the game, recording it for replays
+    fn game_action(
         &mut self,
+        player: &str,
         action: GameAction
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Error> {
+        let game = self.game.as_mut()
+            .ok_or_else(|| Error::InvalidAction(
+                "game hasn't started".to_string()))?;
+        game.action(action.clone())?;
+
+        // keep track of accepted actions for replays
+        if let Some(replay) = &mut self.replay {
+            replay.record(player, Some(action));
+        }
+
+        self.update_turn(false);
+        Ok(())
+    }
+
+    /// restart the turn timer if it's someone else's turn, or if
+    /// forced to
+    fn update_turn(&mut self, force: bool) {
+        let current = self.game.as_ref()
+            .and_then(|game| game.current_player());
+        self.turn = match (current, self.turn_limit, self.turn.take()) {
+            (Some(current), Some(_), Some((player, deadline)))
+                    if current == player && !force => {
+                Some((player, deadline))
+            }
+            (Some(current), Some(limit), _) => {
+                Some((current, Instant::now() + limit))
+            }
+            _ => None,
+        };
+    }
+
+    /// play for the current player if they've run out of time
+    fn check_timeout(&mut self) {
+        let player = match &self.turn {
+            Some((player, deadline)) if Instant::now() >= *deadline => {
+                player.clone()
+            }
+            _ => return,
+        };
+
+        info!("player {:?} ran out of time in room {:?}", player, self.name);
+        let action = self.game.as_ref()
+            .and_then(|game| game.timeout_action());
+        match action {
+            Some(action) if action.0["action"] != TIMEOUT_ACTION => {
+                warn!("timeout action in room {:?} isn't called {:?}",
+                    self.name, TIMEOUT_ACTION);
+            }
+            Some(action) => {
+                if let Err(err) = self.game_action(&player, action) {
+                    warn!("timeout in room {:?} failed: {}", self.name, err);
+                }
+            }
+            None => {
+                info!("no default action in room {:?}, waiting", self.name);
+            }
+        }
+
+        // either way they get a new timer, so we don't spin
+        self.update_turn(true);
+        self.broadcast_state();
+        self.notify_waiting_room();
+        self.save().warn_err().ok();
+    }
+
+    /// a game action from one of our clients
+    fn player_action(
+        &mut self,
+        client: Uuid,
+        mut action: GameAction,
+    ) -> Result<(), Error> {
+        // make sure the client is who they say they are
+        if self.spectators.contains_key(&client) {
+            Err(Error::Spectating)?;
+        }
+        let player = self.client_players.get(&client)
+            .ok_or(Error::NotJoined)?
+            .clone();
+        match action.0.as_object_mut() {
+            Some(fields) => {
+                // only we decide when someone's out of time
+                if fields.get("action")
+                        .map(|name| name == TIMEOUT_ACTION)
+                        .unwrap_or(false) {
+                    Err(Error::InvalidAction(
+                        "only the server can time players out".to_string()))?;
+                }
+                match fields.get("user") {
+                    Some(user) if *user != player => {
+                        Err(Error::WrongPlayer(player.clone()))?;
+                    }
+                    _ => {
+                        fields.insert(
+                            "user".to_string(),
+                            serde_json::json!(player)
+                        );
+                    }
+                }
+            }
+            None => Err(Error::BadMessage(
+                "action is not an object?".to_string()))?,
+        }
+
+        self.game_action(&player, action)
+    }
+
+    fn action(
+        &mut self,
+        client: Uuid,
+        action: GameAction,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
         // intercept non-game specific actions
         let res = match (
             serde_json::from_value::<GameRoomAction>(action.0.clone()),
@@ -163,19 +780,68 @@ impl GameRoom {
         ) {
             (Ok(action), _) => {
                 match action {
-                    GameRoomAction::JoinGame{name} => {
-                        // already a player?
-                        if !self.player_colors.contains_key(&name) {
-                            self.players.push(name.to_string());
-                            // get a new color
-                            self.player_colors.insert(
-                                name.to_string(),
-                                random_color()
-                            );
+                    GameRoomAction::JoinGame{name, token} => {
+                        // the host may share an address with whoever
+                        // they banned, their token is checked below
+                        if self.banned_names.contains(&name)
+                            || (self.host.as_ref() != Some(&name)
+                                && self.client_addrs.get(&client)
+                                    .map(|addr| self.banned_addrs.contains(addr))
+                                    .unwrap_or(false))
+                        {
+                            Err(Error::Banned)?;
+                        }
+                        if self.kicked.contains(&name) {
+                            Err(Error::Kicked)?;
                         }
+
+                        // already a player? then prove it
+                        match self.player_tokens.get(&name) {
+                            Some(expected) => {
+                                if token.as_ref() != Some(expected) {
+                                    Err(Error::NameTaken(name.clone()))?;
+                                }
+                            }
+                            None => {
+                                if self.locked {
+                                    Err(Error::RoomLocked)?;
+                                }
+                                self.check_room_for_player()?;
+                                self.add_player(&name);
+                            }
+                        }
+
+                        // the host left with no one to hand over to,
+                        // so whoever shows up next runs things
+                        if self.host.is_none() {
+                            self.host = Some(name.to_string());
+                        }
+
+                        // remember who this client is
+                        self.spectators.remove(&client);
+                        self.client_players.insert(client, name);
                         Ok(())
                     }
+                    GameRoomAction::Spectate{name} => {
+                        // spectators are never players
+                        self.client_players.remove(&client);
+                        self.spectators.insert(client, name);
+                        Ok(())
+                    }
+                    GameRoomAction::LeaveGame => {
+                        if self.spectators.contains_key(&client) {
+                            Err(Error::Spectating)?;
+                        }
+                        let player = self.client_players.get(&client)
+                            .ok_or(Error::NotJoined)?
+                            .clone();
+
+                        info!("{:?} leaving room {:?}", player, self.name);
+                        self.remove_player(&player)
+                    }
                     GameRoomAction::StartGame => {
+                        self.check_host(client)?;
+
                         // people are definitely going to click this a bunch,
                         // so do nothing if game is already in play
                         if 
@@ -184,18 +850,122 @@ impl GameRoom {
                                 Some(game) => game.ended(),
                             }
                         {
-                            // start the game!
-                            self.game = Some(
-                                self.type_.create(self.players.clone())
-                            );
+                            if self.players.len() < self.type_.min_players() {
+                                Err(Error::NotEnoughPlayers(
+                                    self.type_.min_players()
+                                ))?;
+                            }
+
+                            // start the game! we keep the seed so the game
+                            // can be replayed, only the server gets to pick
+                            // it, otherwise whoever starts the game could
+                            // pick one they like
+                            let seed = self.seed
+                                .unwrap_or_else(|| rand::thread_rng().gen());
+                            let game = self.type_.create(
+                                self.players.clone(),
+                                seed,
+                                &self.options
+                            )?;
+                            self.replay = Some(Replay::new(
+                                self.type_.clone(),
+                                self.players.clone(),
+                                seed,
+                                self.options.clone()
+                            ));
+                            self.game = Some(game);
+                            self.update_turn(true);
+
+                            // a fresh start for anyone who was kicked
+                            self.kicked.clear();
+                        }
+                        Ok(())
+                    }
+                    GameRoomAction::AddBot{kind, name} => {
+                        self.check_host(client)?;
+
+                        self.check_room_for_player()?;
+                        let name = match name {
+                            Some(name) => {
+                                if self.player_tokens.contains_key(&name) {
+                                    Err(Error::NameTaken(name.clone()))?;
+                                }
+                                name
+                            }
+                            None => {
+                                (1..).map(|i| format!("bot-{}", i))
+                                    .find(|name| {
+                                        !self.player_tokens.contains_key(name)
+                                    })
+                                    .unwrap()
+                            }
+                        };
+
+                        self.spawn_bot(&name, kind, ctx)?;
+                        info!("adding {:?} bot {:?} to room {:?}",
+                            kind, name, self.name);
+                        // no one gets the token, so no one can take the
+                        // bot's seat
+                        self.add_player(&name);
+                        self.bots.insert(name, kind);
+                        Ok(())
+                    }
+                    GameRoomAction::Kick{name} => {
+                        self.check_host(client)?;
+                        self.kick(&name)
+                    }
+                    GameRoomAction::Ban{name, address} => {
+                        self.check_host(client)?;
+                        if self.host.as_ref() == Some(&name) {
+                            Err(Error::InvalidAction(
+                                "can't ban the host".to_string()))?;
                         }
+
+                        // find where they are before kicking them
+                        let addrs = self.client_addrs.iter()
+                            .filter(|(uuid, _)| {
+                                self.client_players.get(uuid) == Some(&name)
+                                    || self.spectators.get(uuid) == Some(&name)
+                            })
+                            .map(|(_, addr)| addr.clone())
+                            .collect::<Vec<_>>();
+
+                        // kick them if they're still here
+                        if self.players.contains(&name) {
+                            self.kick(&name)?;
+                        }
+
+                        if address {
+                            self.banned_addrs.extend(addrs);
+                        }
+
+                        info!("banning {:?} from room {:?}", name, self.name);
+                        self.banned_names.insert(name);
+                        Ok(())
+                    }
+                    GameRoomAction::TransferHost{name} => {
+                        self.check_host(client)?;
+                        if !self.players.contains(&name) {
+                            Err(Error::NotPlaying(name.clone()))?;
+                        }
+                        if self.bots.contains_key(&name) {
+                            Err(Error::InvalidAction(
+                                "bots can't be host".to_string()))?;
+                        }
+
+                        self.host = Some(name);
+                        Ok(())
+                    }
+                    GameRoomAction::LockRoom{locked} => {
+                        self.check_host(client)?;
+                        self.locked = locked;
                         Ok(())
                     }
                 }
             }
-            (_, Some(game)) => {
+            (_, Some(_)) => {
                 // ignore that, continue to game action
-                game.action(action)
+                self.player_action(client, action)
             }
             (Err(err), _) => {
                 Err(err)?
@@ -204,12 +974,170 @@ impl GameRoom {
 
         if res.is_ok() {
             self.broadcast_state();
+            self.notify_waiting_room();
+            // a failed save shouldn't stop the game
+            self.save().warn_err().ok();
         }
 
         res
     }
 }
 
+impl Actor for GameRoom {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // bring back any bots
+        for (name, kind) in self.bots.clone() {
+            self.spawn_bot(&name, kind, ctx).warn_err().ok();
+        }
+
+        // find a new host if ours wanders off
+        if self.host_grace_period.is_some() {
+            ctx.run_interval(Duration::from_secs(1), |act, _ctx| {
+                act.check_host_away();
+            });
+        }
+
+        // play for anyone who takes too long
+        if self.turn_limit.is_some() {
+            ctx.run_interval(Duration::from_secs(1), |act, _ctx| {
+                act.check_timeout();
+            });
+        }
+    }
+}
+
+/// a client showing up, they get the room state right away
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct GameRoomConnect {
+    uuid: Uuid,
+    client: Recipient<GameState>,
+    // without the port, for bans
+    addr: String,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct GameRoomDisconnect {
+    uuid: Uuid,
+}
+
+/// an action from one of our clients
+#[derive(Debug, Message)]
+#[rtype(result="Result<(), Error>")]
+struct GameRoomRequest {
+    uuid: Uuid,
+    action: GameAction,
+}
+
+/// replay of the current game, if it's finished
+#[derive(Debug, Message)]
+#[rtype(result="Result<Replay, Error>")]
+struct GameRoomReplay;
+
+/// the room is being destroyed
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct GameRoomClose;
+
+impl Handler<GameRoomConnect> for GameRoom {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: GameRoomConnect,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        // update with room info, we don't know who they are yet
+        msg.client.do_send(self.state_for(None)).warn_err().ok();
+
+        // keep track of clients for broadcasts
+        self.clients.insert(msg.uuid, msg.client);
+        self.client_addrs.insert(msg.uuid, msg.addr);
+        self.notify_waiting_room();
+    }
+}
+
+impl Handler<GameRoomDisconnect> for GameRoom {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: GameRoomDisconnect,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        // keep track of clients for broadcasts
+        self.clients.remove(&msg.uuid);
+        self.client_players.remove(&msg.uuid);
+        self.client_addrs.remove(&msg.uuid);
+        let was_spectator = self.spectators.remove(&msg.uuid).is_some();
+
+        // let everyone know a spectator left
+        if was_spectator {
+            self.broadcast_state();
+        }
+
+        // if all clients have left, the room will be cleaned
+        // up after a grace period, bots don't count
+        self.notify_waiting_room();
+    }
+}
+
+impl Handler<GameRoomRequest> for GameRoom {
+    type Result = Result<(), Error>;
+
+    fn handle(
+        &mut self,
+        msg: GameRoomRequest,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.action(msg.uuid, msg.action, ctx)
+    }
+}
+
+impl Handler<GameRoomReplay> for GameRoom {
+    type Result = Result<Replay, Error>;
+
+    fn handle(
+        &mut self,
+        _msg: GameRoomReplay,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        // only finished games can be exported
+        match (&self.game, &self.replay) {
+            (Some(game), Some(replay)) if game.ended() => Ok(replay.clone()),
+            _ => Err(Error::NoReplay(self.name.clone())),
+        }
+    }
+}
+
+impl Handler<GameRoomClose> for GameRoom {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        _msg: GameRoomClose,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+/// messages that only concern the connection, these never make it
+/// to the room
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag="action", rename_all="snake_case")]
+enum GameRoomSyncAction {
+    // got this state, diff against it from now on
+    Ack {
+        seq: u64,
+    },
+    // lost track, send everything again
+    Resync,
+}
+
 #[derive(Debug)]
 struct GameRoomClient {
     addr: String,
@@ -217,24 +1145,52 @@ struct GameRoomClient {
     heartbeat: Duration,
     heartbeat_last: Instant,
 
-    room_name: String,
-    room: Arc<Mutex<GameRoom>>,
+    room: Addr<GameRoom>,
+    conn: Connection,
+
+    // in delta mode, clients get json patches against the last state
+    // they acked instead of the full state every time
+    delta: Option<DeltaSync>,
 }
 
 impl GameRoomClient {
     fn new(
         addr: &str,
         heartbeat: Duration,
-        room_name: &str,
-        room: Arc<Mutex<GameRoom>>,
+        room: Addr<GameRoom>,
+        conn: Connection,
+        delta: bool,
     ) -> Self {
         Self {
             addr: addr.to_string(),
             uuid: Uuid::new_v4(),
-            heartbeat: heartbeat,
+            heartbeat,
             heartbeat_last: Instant::now(),
-            room_name: room_name.to_string(),
-            room: room,
+            room,
+            conn,
+            delta: if delta { Some(DeltaSync::new()) } else { None },
+        }
+    }
+
+    fn sync_action(
+        &mut self,
+        action: GameRoomSyncAction,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let delta = match &mut self.delta {
+            Some(delta) => delta,
+            None => return,
+        };
+
+        match action {
+            GameRoomSyncAction::Ack{seq} => {
+                delta.ack(seq);
+            }
+            GameRoomSyncAction::Resync => {
+                if let Some(envelope) = delta.resync(&mut self.conn) {
+                    send_envelope(ctx, &envelope);
+                }
+            }
         }
     }
 
@@ -242,22 +1198,42 @@ impl GameRoomClient {
         request: HttpRequest,
         stream: web::Payload,
         opt: web::Data<Opt>,
+        waiting_room: web::Data<Addr<WaitingRoom>>,
         room: web::Path<(String, String)>,
     ) -> actix_web::Result<HttpResponse> {
-        // find game room from global waiting room
-        let room_name = &room.into_inner().0;
-        let room = WAITING_ROOM.lock().unwrap().rooms.get(room_name)
-            .ok_or_else(|| { warn!("can't find room {}", room_name); () })?
-            .clone();
+        // can we even talk to them?
+        let conn = match Connection::negotiate(request.query_string()) {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!("{}", err);
+                return ws::start(Rejected(err), &request, stream);
+            }
+        };
+
+        // find game room from the waiting room, this checks the
+        // password before we upgrade
+        let room_name = room.into_inner().0;
+        let room = waiting_room
+            .send(WaitingRoomGet {
+                room_name: room_name.clone(),
+                password: query_password(&request),
+            })
+            .await
+            .map_err(Error::from)?
+            .map_err(|err| {
+                warn!("can't get into room {}: {}", room_name, err);
+                err
+            })?;
 
         ws::start(
             GameRoomClient::new(
                 request.connection_info()
                     .remote_addr()
-                    .ok_or_else(|| { warn!("no remote addr?"); () })?,
+                    .ok_or_else(|| warn!("no remote addr?"))?,
                 opt.heartbeat,
-                room_name,
                 room,
+                conn,
+                request.query_string().split('&').any(|q| q == "delta"),
             ),
             &request,
             stream
@@ -265,249 +1241,752 @@ impl GameRoomClient {
     }
 }
 
-impl Actor for GameRoomClient {
-    type Context = ws::WebsocketContext<Self>;
+impl Actor for GameRoomClient {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!("client {} connected", self.addr);
+
+        // keep track of clients for broadcasts
+        self.room.do_send(GameRoomConnect {
+            uuid: self.uuid,
+            client: ctx.address().recipient(),
+            addr: self.addr.parse::<std::net::SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|_| self.addr.clone()),
+        });
+            
+        // heartbeat to catch disconnects
+        ctx.run_interval(self.heartbeat, |act, ctx| {
+            if Instant::now()
+                    .duration_since(act.heartbeat_last) > 2*act.heartbeat {
+                ctx.stop();
+                return
+            }
+
+            ctx.ping(b"");
+        });
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        // keep track of clients for broadcasts
+        self.room.do_send(GameRoomDisconnect {
+            uuid: self.uuid,
+        });
+
+        info!("client {} disconnected", self.addr);
+    }
+}
+
+impl StreamHandler<
+    Result<ws::Message, ws::ProtocolError>
+> for GameRoomClient {
+    fn handle(
+        &mut self,
+        msg: Result<ws::Message, ws::ProtocolError>,
+        ctx: &mut Self::Context,
+    ) {
+        // process websocket messages
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                self.heartbeat_last = Instant::now();
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.heartbeat_last = Instant::now();
+            }
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(ws::Message::Text(text)) => {
+                let action = match self.conn.unwrap(&text)
+                    .and_then(|envelope| envelope.into_action())
+                {
+                    Ok(action) => action,
+                    Err(err) => {
+                        reply_error(ctx, &mut self.conn, err, &text);
+                        return;
+                    }
+                };
+
+                if let Ok(action) = serde_json::from_value::<
+                    GameRoomSyncAction
+                >(action.clone()) {
+                    self.sync_action(action, ctx);
+                    return;
+                }
+
+                // the room answers when it gets to us
+                let request = self.room.send(GameRoomRequest {
+                    uuid: self.uuid,
+                    action: GameAction(action),
+                });
+                ctx.spawn(request.into_actor(self)
+                    .map(move |res, act, ctx| {
+                        let res = res.map_err(Error::from)
+                            .and_then(|res| res);
+                        if let Err(err) = res {
+                            reply_error(ctx, &mut act.conn, err, &text);
+                        }
+                    }));
+            }
+            _ => {
+                warn!("bad message {:?}", msg);
+            }
+        }
+    }
+}
+
+/// send a message to a websocket client
+fn send_envelope<A>(ctx: &mut ws::WebsocketContext<A>, envelope: &Envelope)
+where
+    A: Actor<Context=ws::WebsocketContext<A>>
+{
+    match serde_json::to_string(envelope) {
+        Ok(json) => ctx.text(json),
+        Err(err) => warn!("{}", err),
+    }
+}
+
+/// password from a request's query string, if there is one
+fn query_password(request: &HttpRequest) -> Option<String> {
+    web::Query::<HashMap<String, String>>::from_query(request.query_string())
+        .ok()
+        .and_then(|query| query.get("password").cloned())
+}
+
+/// let only the client that sent a message know what went wrong
+fn reply_error<A>(
+    ctx: &mut ws::WebsocketContext<A>,
+    conn: &mut Connection,
+    err: Error,
+    text: &str,
+)
+where
+    A: Actor<Context=ws::WebsocketContext<A>>
+{
+    // not the message itself, it may have a password in it
+    warn!("{}", err);
+
+    match serde_json::to_value(ErrorReply::new(err, text)) {
+        Ok(reply) => send_envelope(ctx, &conn.wrap("error", reply)),
+        Err(err) => warn!("{}", err),
+    }
+}
+
+impl Handler<GameState> for GameRoomClient {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: GameState,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        // broadcast updates to all connected clients
+        let envelope = match &mut self.delta {
+            Some(delta) => delta.sync(&mut self.conn, msg.0),
+            None => self.conn.wrap("state", msg.0),
+        };
+        send_envelope(ctx, &envelope);
+    }
+}
+
+
+// -- waiting room management --
+
+/// what the waiting room knows about each room
+#[derive(Debug)]
+struct WaitingRoomEntry {
+    addr: Addr<GameRoom>,
+    // last thing the room told us
+    state: GameRoomState,
+    // when the last client left, empty rooms are kept around for a
+    // grace period so players can reconnect
+    empty_since: Option<Instant>,
+    // restored from the store and nobody's come back yet
+    restored: bool,
+    password: Option<PasswordHash>,
+    // private rooms are never broadcast
+    private: bool,
+}
+
+/// landing page is a simple waiting room
+#[derive(Debug)]
+struct WaitingRoom {
+    rooms: HashMap<String, WaitingRoomEntry>,
+    waiters: HashMap<Uuid, Recipient<WaitingRoomEvent>>,
+    store: Option<Arc<dyn Store>>,
+    // fixed seed for every game, for reproducing bugs
+    seed: Option<u64>,
+    // turn limit for rooms that don't pick one
+    turn_limit: Option<Duration>,
+
+    // how often to look for empty rooms, and how long to keep them
+    reap_interval: Duration,
+    grace_period: Duration,
+    // restored rooms get longer, everyone needs to notice the server
+    // is back first
+    restore_grace_period: Duration,
+
+    // rooms are spread across these so they can run in parallel
+    arbiters: Vec<Arbiter>,
+    next_arbiter: usize,
+}
+
+impl WaitingRoom {
+    fn new(reap_interval: Duration, grace_period: Duration) -> Self {
+        Self {
+            rooms: HashMap::new(),
+            waiters: HashMap::new(),
+            store: None,
+            seed: None,
+            turn_limit: None,
+            reap_interval,
+            grace_period,
+            restore_grace_period: grace_period,
+            arbiters: Vec::new(),
+            next_arbiter: 0,
+        }
+    }
+
+    fn insert_room(&mut self, room_name: &str, room: GameRoom, restored: bool) {
+        let state = room.room_state();
+        let password = room.password.clone();
+        let private = room.private;
+        let arbiter = &self.arbiters[self.next_arbiter % self.arbiters.len()];
+        self.next_arbiter += 1;
+        let addr = GameRoom::start_in_arbiter(arbiter, move |_| room);
+
+        self.rooms.insert(room_name.to_string(), WaitingRoomEntry {
+            addr,
+            state: state.clone(),
+            empty_since: Some(Instant::now()),
+            restored,
+            password,
+            private,
+        });
+
+        if !private {
+            self.broadcast(WaitingRoomEvent::Created {
+                room_name: room_name.to_string(),
+                room: state,
+            });
+        }
+    }
+
+    /// start saving rooms to the store, restoring any rooms
+    /// that were already there
+    fn restore(
+        &mut self,
+        store: Arc<dyn Store>,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        for (room_name, snapshot) in store.load()? {
+            let mut room = match
+                serde_json::from_value::<GameRoomSnapshot>(snapshot)
+                    .map_err(Error::from)
+                    .and_then(|snapshot| {
+                        GameRoom::restore(
+                            &room_name,
+                            snapshot,
+                            Some(store.clone()),
+                            ctx.address(),
+                        )
+                    })
+            {
+                Ok(room) => room,
+                Err(err) => {
+                    warn!("can't restore room {:?}: {}", room_name, err);
+                    continue;
+                }
+            };
+
+            info!("restored room {:?} type {:?}", room_name, room.type_);
+            room.seed = self.seed;
+            room.host_grace_period = Some(self.grace_period);
+            self.insert_room(&room_name, room, true);
+        }
+
+        self.store = Some(store);
+        Ok(())
+    }
+
+    fn state(&self) -> WaitingRoomEvent {
+        WaitingRoomEvent::Rooms {
+            rooms: self.rooms.iter()
+                .filter(|(_, room)| !room.private)
+                .map(|(name, room)| (name.to_string(), room.state.clone()))
+                .collect()
+        }
+    }
+
+    /// let everyone know what changed, clients get the full listing
+    /// when they connect and keep it up to date with these
+    fn broadcast(&self, event: WaitingRoomEvent) {
+        for (_, client) in self.waiters.iter() {
+            client.do_send(event.clone()).warn_err().ok();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_room(
+        &mut self,
+        room_name: &str,
+        room_type: GameType,
+        host: &str,
+        options: serde_json::Value,
+        turn_limit: Option<Duration>,
+        password: Option<String>,
+        private: bool,
+        ctx: &mut Context<Self>,
+    ) -> Result<String, Error> {
+        // keep track of rooms
+        if room_name.is_empty() {
+            Err(Error::NoRoomName)?;
+        }
+
+        if host.is_empty() {
+            Err(Error::NoHostName)?;
+        }
+
+        if self.rooms.contains_key(room_name) {
+            Err(Error::RoomExists(room_name.to_string()))?;
+        }
+
+        let options = room_type.options(options)?;
+
+        let mut room = GameRoom::new(
+            room_name,
+            room_type,
+            self.store.clone(),
+            ctx.address()
+        );
+        room.options = options;
+        room.seed = self.seed;
+        room.turn_limit = turn_limit.or(self.turn_limit);
+        room.password = password.map(|password| PasswordHash::new(&password));
+        room.private = private;
+        // the host gets the first seat, and the token to claim it
+        let token = room.add_player(host);
+        room.host = Some(host.to_string());
+        room.host_grace_period = Some(self.grace_period);
+        room.save()?;
+        self.insert_room(room_name, room, false);
+
+        Ok(token)
+    }
+
+    fn destroy_room(
+        &mut self,
+        room_name: &str,
+    ) -> Result<(), Error> {
+        let room = self.rooms.remove(room_name)
+            .ok_or_else(|| Error::NoSuchRoom(room_name.to_string()))?;
+        room.addr.do_send(GameRoomClose);
+
+        if !room.private {
+            self.broadcast(WaitingRoomEvent::Destroyed {
+                room_name: room_name.to_string(),
+            });
+        }
+
+        // nobody came back after a restart, but they might after the
+        // next one, so keep the save
+        if room.restored {
+            info!("keeping save for room {:?}", room_name);
+            return Ok(());
+        }
+
+        if let Some(store) = &self.store {
+            store.remove(room_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// destroy rooms that have been empty for longer than the
+    /// grace period
+    fn reap(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let expired = self.rooms.iter()
+            .filter(|(_, room)| {
+                let grace_period = if room.restored {
+                    self.restore_grace_period
+                } else {
+                    self.grace_period
+                };
+                match room.empty_since {
+                    Some(empty_since) => {
+                        now.duration_since(empty_since) > grace_period
+                    }
+                    None => false,
+                }
+            })
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+
+        for room_name in expired {
+            ctx.notify(WaitingRoomDestroy { room_name });
+        }
+    }
+
+    fn action(
+        &mut self,
+        action: WaitingRoomAction,
+        ctx: &mut Context<Self>,
+    ) -> Result<Option<WaitingRoomEvent>, Error> {
+        match action {
+            WaitingRoomAction::CreateRoom{
+                room_name, room_type, host, options, turn_limit, password,
+                private
+            } => {
+                info!("creating room {:?} type {:?}", room_name, room_type);
+                let token = self.create_room(
+                    &room_name,
+                    room_type,
+                    &host,
+                    options,
+                    turn_limit.map(Duration::from_secs),
+                    // an empty password is no password
+                    password.filter(|password| !password.is_empty()),
+                    private,
+                    ctx
+                )?;
+
+                // only the creator hears about this
+                Ok(Some(WaitingRoomEvent::Hosting {
+                    room_name,
+                    name: host,
+                    token,
+                }))
+            }
+        }
+    }
+}
+
+impl Actor for WaitingRoom {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // one arbiter per core seems reasonable
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        self.arbiters = (0..threads).map(|_| Arbiter::new()).collect();
+
+        // clean up empty rooms
+        ctx.run_interval(self.reap_interval, |act, ctx| {
+            act.reap(ctx);
+        });
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Message)]
+#[serde(tag="action", rename_all="snake_case")]
+#[rtype(result="Result<Option<WaitingRoomEvent>, Error>")]
+enum WaitingRoomAction {
+    CreateRoom {
+        room_name: String,
+        room_type: GameType,
+        // who's creating the room, they're its host
+        host: String,
+        // checked against the game's options schema
+        #[serde(default)]
+        options: serde_json::Value,
+        // seconds per turn
+        #[serde(default)]
+        turn_limit: Option<u64>,
+        #[serde(default)]
+        password: Option<String>,
+        // leave out of the listing?
+        #[serde(default)]
+        private: bool,
+    },
+}
+
+/// destroy a room, only the waiting room itself sends this, never
+/// clients
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct WaitingRoomDestroy {
+    room_name: String,
+}
+
+/// the room listing, and incremental changes to it, plus replies
+/// only sent to whoever asked
+#[derive(Debug, Message, Serialize, Deserialize, Clone)]
+#[serde(tag="type", content="payload")]
+#[rtype(result="()")]
+enum WaitingRoomEvent {
+    #[serde(rename="rooms")]
+    Rooms {
+        rooms: HashMap<String, GameRoomState>,
+    },
+    #[serde(rename="room_created")]
+    Created {
+        room_name: String,
+        room: GameRoomState,
+    },
+    #[serde(rename="room_updated")]
+    Updated {
+        room_name: String,
+        room: GameRoomState,
+    },
+    #[serde(rename="room_destroyed")]
+    Destroyed {
+        room_name: String,
+    },
+    // the session token for the host's seat in a room they created
+    #[serde(rename="room_hosting")]
+    Hosting {
+        room_name: String,
+        name: String,
+        token: String,
+    },
+}
+
+/// start saving rooms to the store, restoring any saved rooms
+#[derive(Debug, Message)]
+#[rtype(result="Result<(), Error>")]
+struct WaitingRoomRestore(Arc<dyn Store>);
+
+/// a client showing up, they get the listing right away
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct WaitingRoomConnect {
+    uuid: Uuid,
+    client: Recipient<WaitingRoomEvent>,
+}
+
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct WaitingRoomDisconnect {
+    uuid: Uuid,
+}
 
-    fn started(&mut self, ctx: &mut Self::Context) {
-        info!("client {} connected", self.addr);
+/// find a room by name, checking the password if it has one
+#[derive(Debug, Message)]
+#[rtype(result="Result<Addr<GameRoom>, Error>")]
+struct WaitingRoomGet {
+    room_name: String,
+    password: Option<String>,
+}
 
-        // keep track of clients for broadcasts
-        self.room.lock().unwrap().clients.insert(
-            self.uuid, 
-            ctx.address().recipient(),
-        );
-            
-        // heartbeat to catch disconnects
-        ctx.run_interval(self.heartbeat, |act, ctx| {
-            if Instant::now()
-                    .duration_since(act.heartbeat_last) > 2*act.heartbeat {
-                ctx.stop();
-                return
-            }
+/// rooms let us know whenever something changes
+#[derive(Debug, Message)]
+#[rtype(result="()")]
+struct GameRoomUpdate {
+    room_name: String,
+    state: GameRoomState,
+    // no one but bots around?
+    empty: bool,
+}
 
-            ctx.ping(b"");
-        });
+impl Handler<WaitingRoomAction> for WaitingRoom {
+    type Result = Result<Option<WaitingRoomEvent>, Error>;
 
-        // update with room info
-        ctx.address().do_send(self.room.lock().unwrap().state());
+    fn handle(
+        &mut self,
+        msg: WaitingRoomAction,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.action(msg, ctx)
     }
+}
 
-    fn stopped(&mut self, _ctx: &mut Self::Context) {
-        let is_empty = {
-            // keep track of waiter for broadcasts
-            let mut room = self.room.lock().unwrap();
-            room.clients.remove(&self.uuid);
-            room.clients.is_empty()
-        };
-
-        // clean up room if all clients have left
-        if is_empty {
-            match
-                WAITING_ROOM.lock().unwrap()
-                    .action(WaitingRoomAction::DestroyRoom {
-                        room_name: self.room_name.clone()
-                    })
-            {
-                Ok(()) => {},
-                Err(err) => {
-                    warn!("{}", err);
-                }
-            }
-        }
+impl Handler<WaitingRoomRestore> for WaitingRoom {
+    type Result = Result<(), Error>;
 
-        info!("client {} disconnected", self.addr);
+    fn handle(
+        &mut self,
+        msg: WaitingRoomRestore,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.restore(msg.0, ctx)
     }
 }
 
-impl StreamHandler<
-    Result<ws::Message, ws::ProtocolError>
-> for GameRoomClient {
+impl Handler<WaitingRoomDestroy> for WaitingRoom {
+    type Result = ();
+
     fn handle(
         &mut self,
-        msg: Result<ws::Message, ws::ProtocolError>,
-        ctx: &mut Self::Context,
-    ) {
-        // process websocket messages
-        match msg {
-            Ok(ws::Message::Ping(msg)) => {
-                self.heartbeat_last = Instant::now();
-                ctx.pong(&msg);
-            }
-            Ok(ws::Message::Pong(_)) => {
-                self.heartbeat_last = Instant::now();
-            }
-            Ok(ws::Message::Close(reason)) => {
-                ctx.close(reason);
-                ctx.stop();
-            }
-            Ok(ws::Message::Text(ref text)) => {
-                match
-                    serde_json::from_str::<GameAction>(&text)
-                        .map_err(|err| -> Box<dyn std::error::Error> {
-                            Box::new(err)
-                        })
-                        .and_then(|action| {
-                            self.room.lock().unwrap().action(action)
-                        })
-                        .and_then(|_| {
-                            // also broadcast game room updates to waiting room,
-                            // this is sort of a hack
-                            WAITING_ROOM.lock().unwrap().broadcast_state();
-                            Ok(())
-                        })
-                {
-                    Ok(()) => (),
-                    Err(err) => {
-                        warn!("bad message {:?}", &msg);
-                        warn!("{}", err);
-                    }
-                };
-            }
-            _ => {
-                warn!("bad message {:?}", msg);
-            }
-        }
+        msg: WaitingRoomDestroy,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        info!("destroying room {:?}", msg.room_name);
+        self.destroy_room(&msg.room_name).warn_err().ok();
     }
 }
 
-impl Handler<GameState> for GameRoomClient {
+impl Handler<WaitingRoomConnect> for WaitingRoom {
     type Result = ();
 
     fn handle(
         &mut self,
-        msg: GameState,
-        ctx: &mut Self::Context
+        msg: WaitingRoomConnect,
+        _ctx: &mut Self::Context
     ) -> Self::Result {
-        // broadcast updates to all connected clients
-        let json = match serde_json::to_string(&msg) {
-            Ok(json) => json,
-            Err(err) => {
-                warn!("{}", err);
-                return
-            }
-        };
+        // update with room info
+        msg.client.do_send(self.state()).warn_err().ok();
 
-        ctx.text(json);
+        // keep track of waiter for broadcasts
+        self.waiters.insert(msg.uuid, msg.client);
     }
 }
 
+impl Handler<WaitingRoomDisconnect> for WaitingRoom {
+    type Result = ();
 
-//// waiting room management ////
-
-/// landing page is a simple waiting room
-#[derive(Debug)]
-struct WaitingRoom {
-    rooms: HashMap<String, Arc<Mutex<GameRoom>>>,
-    waiters: HashMap<Uuid, Recipient<WaitingRoomState>>,
+    fn handle(
+        &mut self,
+        msg: WaitingRoomDisconnect,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        self.waiters.remove(&msg.uuid);
+    }
 }
 
-impl WaitingRoom {
-    fn new() -> Self {
-        Self {
-            rooms: HashMap::new(),
-            waiters: HashMap::new(),
+impl Handler<WaitingRoomGet> for WaitingRoom {
+    type Result = Result<Addr<GameRoom>, Error>;
+
+    fn handle(
+        &mut self,
+        msg: WaitingRoomGet,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        let room = self.rooms.get(&msg.room_name)
+            .ok_or_else(|| Error::NoSuchRoom(msg.room_name.clone()))?;
+
+        if let Some(hash) = &room.password {
+            let ok = msg.password
+                .map(|password| hash.check(&password))
+                .unwrap_or(false);
+            if !ok {
+                Err(Error::WrongPassword(msg.room_name))?;
+            }
         }
-    }
 
-    fn state(&self) -> WaitingRoomState {
-        WaitingRoomState(serde_json::json!({
-            "rooms": self.rooms.iter()
-                .map(|(name, room)| (
-                    name.to_string(),
-                    room.lock().unwrap().room_state()
-                ))
-                .collect::<HashMap<_, _>>()
-        }))
+        Ok(room.addr.clone())
     }
+}
 
-    fn broadcast_state(&self) {
-        // broadcast update
-        let state = self.state();
-        for (_, client) in self.waiters.iter() {
-            client.do_send(state.clone()).warn_err().ok();
-        }
-    }
+impl Handler<GameRoomUpdate> for WaitingRoom {
+    type Result = ();
 
-    fn create_room(
+    fn handle(
         &mut self,
-        room_name: &str,
-        room_type: GameType
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // keep track of rooms
-        if room_name.len() == 0 {
-            Err(format!("can't create room without name"))?;
-        }
+        msg: GameRoomUpdate,
+        _ctx: &mut Self::Context
+    ) -> Self::Result {
+        // may have been destroyed in the meantime
+        let room = match self.rooms.get_mut(&msg.room_name) {
+            Some(room) => room,
+            None => return,
+        };
 
-        if self.rooms.contains_key(room_name) {
-            Err(format!("room already exists {:?}", room_name))?;
+        room.empty_since = match (msg.empty, room.empty_since) {
+            (true, Some(empty_since)) => Some(empty_since),
+            (true, None) => Some(Instant::now()),
+            (false, _) => None,
+        };
+        if !msg.empty {
+            room.restored = false;
         }
 
-        self.rooms.insert(
-            room_name.to_string(),
-            Arc::new(Mutex::new(GameRoom::new(room_type)))
-        );
+        // most game actions don't change anything the waiting
+        // room cares about
+        if room.state == msg.state {
+            return;
+        }
 
-        Ok(())
+        room.state = msg.state.clone();
+        if !room.private {
+            self.broadcast(WaitingRoomEvent::Updated {
+                room_name: msg.room_name,
+                room: msg.state,
+            });
+        }
     }
+}
 
-    fn destroy_room(
-        &mut self,
-        room_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        match self.rooms.remove(room_name) {
-            Some(_) => (),
-            None => Err(format!("room does not exist? {:?}", room_name))?,
-        }
+/// a bot sitting in a room, as far as the room is concerned this is
+/// just another client
+#[derive(Debug)]
+struct BotClient {
+    uuid: Uuid,
+    name: String,
+    bot: Box<dyn Bot>,
+    room: Addr<GameRoom>,
 
-        Ok(())
+    // latest game state, and if we're already about to act on it
+    state: Option<GameState>,
+    thinking: bool,
+}
+
+impl BotClient {
+    fn new(
+        uuid: Uuid,
+        name: &str,
+        bot: Box<dyn Bot>,
+        room: Addr<GameRoom>,
+    ) -> Self {
+        Self {
+            uuid,
+            name: name.to_string(),
+            bot,
+            room,
+            state: None,
+            thinking: false,
+        }
     }
 
-    fn action(
-        &mut self,
-        action: WaitingRoomAction
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let res = match action {
-            WaitingRoomAction::CreateRoom{room_name, room_type} => {
-                info!("creating room {:?} type {:?}", room_name, room_type);
-                self.create_room(&room_name, room_type)
-            }
-            WaitingRoomAction::DestroyRoom{room_name} => {
-                info!("destroying room {:?}", room_name);
-                self.destroy_room(&room_name)
-            }
+    fn think(&mut self, ctx: &mut Context<Self>) {
+        self.thinking = false;
+        let action = match &self.state {
+            Some(state) => self.bot.act(&self.name, state),
+            None => None,
         };
 
-        if res.is_ok() {
-            self.broadcast_state();
+        if let Some(action) = action {
+            // same path as everyone else
+            let request = self.room.send(GameRoomRequest {
+                uuid: self.uuid,
+                action,
+            });
+            ctx.spawn(request.into_actor(self).map(|res, act, _ctx| {
+                let res = res.map_err(Error::from).and_then(|res| res);
+                if let Err(err) = res {
+                    warn!("bot {:?} made a bad move: {}", act.name, err);
+                }
+            }));
         }
-
-        res
     }
 }
 
-lazy_static! {
-    /// global waiting room state
-    static ref WAITING_ROOM: Mutex<WaitingRoom> = {
-        Mutex::new(WaitingRoom::new())
-    };
+impl Actor for BotClient {
+    type Context = Context<Self>;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag="action", rename_all="snake_case")]
-enum WaitingRoomAction {
-    CreateRoom {
-        room_name: String,
-        room_type: GameType,
-    },
-    DestroyRoom {
-        room_name: String,
-    },
-}
+impl Handler<GameState> for BotClient {
+    type Result = ();
 
-#[derive(Debug, Message, Serialize, Deserialize, Clone)]
-#[serde(rename_all="snake_case")]
-#[rtype(result="()")]
-struct WaitingRoomState(serde_json::Value);
+    fn handle(
+        &mut self,
+        msg: GameState,
+        ctx: &mut Self::Context
+    ) -> Self::Result {
+        // wait a bit before acting, so humans can follow along
+        self.state = Some(msg);
+        if !self.thinking {
+            self.thinking = true;
+            ctx.run_later(Duration::from_millis(500), |act, ctx| {
+                act.think(ctx);
+            });
+        }
+    }
+}
 
 #[derive(Debug)]
 struct WaitingRoomClient {
@@ -515,15 +1994,36 @@ struct WaitingRoomClient {
     uuid: Uuid,
     heartbeat: Duration,
     heartbeat_last: Instant,
+
+    waiting_room: Addr<WaitingRoom>,
+    conn: Connection,
 }
 
 impl WaitingRoomClient {
-    fn new(addr: &str, heartbeat: Duration) -> Self {
+    fn new(
+        addr: &str,
+        heartbeat: Duration,
+        waiting_room: Addr<WaitingRoom>,
+        conn: Connection,
+    ) -> Self {
         Self {
             addr: addr.to_string(),
             uuid: Uuid::new_v4(),
-            heartbeat: heartbeat,
+            heartbeat,
             heartbeat_last: Instant::now(),
+            waiting_room,
+            conn,
+        }
+    }
+
+    fn send_event(
+        &mut self,
+        ctx: &mut ws::WebsocketContext<Self>,
+        event: &WaitingRoomEvent,
+    ) {
+        match self.conn.wrap_message(event) {
+            Ok(envelope) => send_envelope(ctx, &envelope),
+            Err(err) => warn!("{}", err),
         }
     }
 
@@ -531,13 +2031,25 @@ impl WaitingRoomClient {
         request: HttpRequest,
         stream: web::Payload,
         opt: web::Data<Opt>,
+        waiting_room: web::Data<Addr<WaitingRoom>>,
     ) -> actix_web::Result<HttpResponse> {
+        // can we even talk to them?
+        let conn = match Connection::negotiate(request.query_string()) {
+            Ok(conn) => conn,
+            Err(err) => {
+                warn!("{}", err);
+                return ws::start(Rejected(err), &request, stream);
+            }
+        };
+
         ws::start(
             WaitingRoomClient::new(
                 request.connection_info()
                     .remote_addr()
-                    .ok_or_else(|| { warn!("no remote addr?"); () })?,
+                    .ok_or_else(|| warn!("no remote addr?"))?,
                 opt.heartbeat,
+                waiting_room.get_ref().clone(),
+                conn,
             ),
             &request,
             stream
@@ -552,10 +2064,10 @@ impl Actor for WaitingRoomClient {
         info!("client {} connected", self.addr);
 
         // keep track of waiter for broadcasts
-        WAITING_ROOM.lock().unwrap().waiters.insert(
-            self.uuid,
-            ctx.address().recipient(),
-        );
+        self.waiting_room.do_send(WaitingRoomConnect {
+            uuid: self.uuid,
+            client: ctx.address().recipient(),
+        });
             
         // heartbeat to catch disconnects
         ctx.run_interval(self.heartbeat, |act, ctx| {
@@ -567,14 +2079,13 @@ impl Actor for WaitingRoomClient {
 
             ctx.ping(b"");
         });
-
-        // update with room info
-        ctx.address().do_send(WAITING_ROOM.lock().unwrap().state());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         // keep track of waiter for broadcasts
-        WAITING_ROOM.lock().unwrap().waiters.remove(&self.uuid);
+        self.waiting_room.do_send(WaitingRoomDisconnect {
+            uuid: self.uuid,
+        });
         info!("client {} disconnected", self.addr);
     }
 }
@@ -600,22 +2111,38 @@ impl StreamHandler<
                 ctx.close(reason);
                 ctx.stop();
             }
-            Ok(ws::Message::Text(ref text)) => {
-                match
-                    serde_json::from_str(&text)
-                        .map_err(|err| -> Box<dyn std::error::Error> {
-                            Box::new(err)
-                        })
-                        .and_then(|action| {
-                            WAITING_ROOM.lock().unwrap().action(action)
-                        })
-                {
-                    Ok(()) => (),
+            Ok(ws::Message::Text(text)) => {
+                let action = self.conn.unwrap(&text)
+                    .and_then(|envelope| envelope.into_action())
+                    .and_then(|action| {
+                        Ok(serde_json::from_value::<WaitingRoomAction>(
+                            action
+                        )?)
+                    });
+
+                match action {
+                    Ok(action) => {
+                        // the waiting room answers when it gets to us
+                        let request = self.waiting_room.send(action);
+                        ctx.spawn(request.into_actor(self)
+                            .map(move |res, act, ctx| {
+                                let res = res.map_err(Error::from)
+                                    .and_then(|res| res);
+                                match res {
+                                    Ok(Some(reply)) => {
+                                        act.send_event(ctx, &reply);
+                                    }
+                                    Ok(None) => {}
+                                    Err(err) => {
+                                        reply_error(ctx, &mut act.conn, err, &text);
+                                    }
+                                }
+                            }));
+                    }
                     Err(err) => {
-                        warn!("bad message {:?}", &msg);
-                        warn!("{}", err);
+                        reply_error(ctx, &mut self.conn, err, &text);
                     }
-                };
+                }
             }
             _ => {
                 warn!("bad message {:?}", msg);
@@ -624,29 +2151,21 @@ impl StreamHandler<
     }
 }
 
-impl Handler<WaitingRoomState> for WaitingRoomClient {
+impl Handler<WaitingRoomEvent> for WaitingRoomClient {
     type Result = ();
 
     fn handle(
         &mut self,
-        msg: WaitingRoomState,
+        msg: WaitingRoomEvent,
         ctx: &mut Self::Context
     ) -> Self::Result {
         // broadcast updates to all connected clients
-        let json = match serde_json::to_string(&msg) {
-            Ok(json) => json,
-            Err(err) => {
-                warn!("{}", err);
-                return
-            }
-        };
-
-        ctx.text(json);
+        self.send_event(ctx, &msg);
     }
 }
 
 
-//// entry point below ////
+// -- entry point below --
 fn parse_duration(s: &str) -> Result<Duration, ParseIntError> {
     Ok(Duration::from_secs(s.parse::<u64>()?))
 }
@@ -664,6 +2183,51 @@ struct Opt {
         parse(try_from_str=parse_duration)
     )]
     heartbeat: Duration,
+
+    /// How long to keep empty rooms around in seconds, so players
+    /// can reconnect
+    #[structopt(
+        long, default_value="60",
+        parse(try_from_str=parse_duration)
+    )]
+    grace_period: Duration,
+
+    /// How long to keep rooms restored from --save-dir around in
+    /// seconds if nobody comes back to them, their saves are kept
+    /// either way
+    #[structopt(
+        long, default_value="86400",
+        parse(try_from_str=parse_duration)
+    )]
+    restore_grace_period: Duration,
+
+    /// Directory to save rooms in, so games survive restarts
+    #[structopt(long, parse(from_os_str))]
+    save_dir: Option<PathBuf>,
+
+    /// Seed every game with this, for reproducing games
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Default time limit for each turn in seconds, rooms can pick
+    /// their own
+    #[structopt(long, parse(try_from_str=parse_duration))]
+    turn_limit: Option<Duration>,
+
+    /// Play back a replay file, printing every intermediate state
+    /// instead of running the server
+    #[structopt(long, parse(from_os_str))]
+    replay: Option<PathBuf>,
+
+    /// Player whose view to print when playing back a replay,
+    /// defaults to the public view
+    #[structopt(long)]
+    replay_as: Option<String>,
+
+    /// Directory of .wasm games to load at startup, their assets are
+    /// served from /games
+    #[structopt(long, parse(from_os_str))]
+    games_dir: Option<PathBuf>,
 }
 
 trait ErrorEx {
@@ -680,12 +2244,15 @@ impl<T, E: std::fmt::Display> ErrorEx for Result<T, E> {
 }
 
 #[actix_web::get("/")]
-async fn waiting_room() -> actix_web::Result<HttpResponse> {
+async fn waiting_room_page() -> actix_web::Result<HttpResponse> {
     let body = std::fs::read("static/waiting-room.html").warn_err()?;
     let body = String::from_utf8_lossy(&body)
         .replace(
             "ROOM_TYPES",
-            &serde_json::to_string(&GameType::names()).warn_err()?
+            &serde_json::to_string(&GameType::all().iter()
+                .map(|type_| type_.info())
+                .collect::<Vec<_>>()
+            ).warn_err()?
         )
         // landing page gets a random color, because why not
         .replace(
@@ -695,6 +2262,32 @@ async fn waiting_room() -> actix_web::Result<HttpResponse> {
     Ok(HttpResponse::Ok().body(body))
 }
 
+#[actix_web::get("/replay/{room}")]
+async fn game_replay(
+    request: HttpRequest,
+    waiting_room: web::Data<Addr<WaitingRoom>>,
+    room: web::Path<String>
+) -> actix_web::Result<HttpResponse> {
+    let room_name = room.into_inner();
+    let room = waiting_room
+        .send(WaitingRoomGet {
+            room_name: room_name.clone(),
+            password: query_password(&request),
+        })
+        .await
+        .map_err(Error::from)??;
+
+    // only finished games can be exported
+    let replay = room.send(GameRoomReplay).await.map_err(Error::from)??;
+    Ok(HttpResponse::Ok()
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename={:?}",
+                format!("{}.replay.json", room_name))
+        )
+        .json(replay))
+}
+
 #[actix_web::get("/room/{room}/{user}")]
 async fn game_room(
     room: web::Path<(String, String)>
@@ -717,18 +2310,68 @@ async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "info");
     env_logger::init();
 
+    // extra games, these need to be around before anything refers
+    // to them
+    if let Some(games_dir) = &opt.games_dir {
+        info!("loading games from {:?}", games_dir);
+        wasm_game::load_games(games_dir)
+            .map_err(std::io::Error::other)?;
+    }
+
+    // just playing back a replay?
+    if let Some(path) = &opt.replay {
+        let replay = serde_json::from_slice::<Replay>(&std::fs::read(path)?)?;
+        let states = replay.states(opt.replay_as.as_deref())
+            .map_err(std::io::Error::other)?;
+        for state in states {
+            println!("{}", serde_json::to_string(&state)?);
+        }
+        return Ok(());
+    }
+
+    // empty rooms are checked for every heartbeat
+    let mut waiting_room = WaitingRoom::new(opt.heartbeat, opt.grace_period);
+    waiting_room.restore_grace_period = opt.restore_grace_period;
+
+    if let Some(seed) = opt.seed {
+        info!("seeding every game with {}", seed);
+        waiting_room.seed = Some(seed);
+    }
+
+    if let Some(turn_limit) = opt.turn_limit {
+        info!("limiting turns to {:?}", turn_limit);
+        waiting_room.turn_limit = Some(turn_limit);
+    }
+
+    let waiting_room = waiting_room.start();
+
+    // restore any saved rooms
+    if let Some(save_dir) = &opt.save_dir {
+        info!("saving rooms to {:?}", save_dir);
+        let store = FileStore::new(save_dir.clone())
+            .map_err(std::io::Error::other)?;
+        waiting_room.send(WaitingRoomRestore(Arc::new(store))).await
+            .map_err(std::io::Error::other)?
+            .map_err(std::io::Error::other)?;
+    }
+
     info!("launching server on {}", addr);
 
     // launch server
     HttpServer::new(move || {
         App::new()
-            // enable logger
-            .wrap(middleware::Logger::default())
+            // enable logger, leaving out query strings and referers,
+            // which may have room passwords in them
+            .wrap(middleware::Logger::new(
+                "%a \"%U\" %s %b \"%{User-Agent}i\" %T"
+            ))
             // pass options
             .data(opt.clone())
+            .data(waiting_room.clone())
             // dynamic files
-            .service(waiting_room)
+            .service(waiting_room_page)
             .service(game_room)
+            .service(game_replay)
             // websocket routes
             .service(
                 web::resource("/ws")
@@ -738,10 +2381,231 @@ async fn main() -> std::io::Result<()> {
                 web::resource("/room/{room}/{user}/ws")
                     .route(web::get().to(GameRoomClient::get))
             )
-            // static files
+            // static files, games loaded at startup bring their own
+            .configure(|cfg| {
+                if let Some(games_dir) = &opt.games_dir {
+                    cfg.service(fs::Files::new("/games", games_dir));
+                }
+            })
             .service(fs::Files::new("/", "static/"))
     })
     .bind(addr)?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a room with a game of test_game going between the given players
+    fn room_in_game(players: &[&str]) -> GameRoom {
+        let waiting_room = WaitingRoom::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60)
+        ).start();
+        let type_ = GameType::get("test_game").unwrap();
+        let mut room = GameRoom::new("room", type_.clone(), None, waiting_room);
+        room.players = players.iter().map(|player| player.to_string()).collect();
+        room.host = Some(players[0].to_string());
+        room.game = Some(type_.create(room.players.clone(), 0, &room.options).unwrap());
+        room.replay = Some(Replay::new(type_, room.players.clone(), 0, room.options.clone()));
+        room
+    }
+
+    #[test]
+    fn late_joiners_can_leave() {
+        System::new("test").block_on(async {
+            let mut room = room_in_game(&["a", "b", "c"]);
+            room.players.push("d".to_string());
+
+            // d isn't in the game, so it has nothing to say about it
+            room.remove_player("d").unwrap();
+            assert_eq!(room.players, vec!["a", "b", "c"]);
+            assert_eq!(room.replay.as_ref().unwrap().steps.len(), 0);
+            assert!(!room.game.as_ref().unwrap().ended());
+
+            // but people in the game still leave it
+            room.kick("b").unwrap();
+            assert_eq!(room.players, vec!["a", "c"]);
+            assert_eq!(room.replay.as_ref().unwrap().steps.len(), 1);
+
+            // and rejoining under the same name doesn't put them back in
+            room.players.push("b".to_string());
+            room.kick("b").unwrap();
+            assert_eq!(room.players, vec!["a", "c"]);
+            assert_eq!(room.replay.as_ref().unwrap().steps.len(), 1);
+        });
+    }
+
+    #[test]
+    fn clients_cant_time_out() {
+        System::new("test").block_on(async {
+            let mut room = room_in_game(&["a", "b"]);
+            let current = room.game.as_ref().unwrap().current_player().unwrap();
+            let client = Uuid::new_v4();
+            room.client_players.insert(client, current.clone());
+
+            let timeout = room.game.as_ref().unwrap().timeout_action().unwrap();
+            assert!(matches!(
+                room.player_action(client, timeout.clone()),
+                Err(Error::InvalidAction(_))
+            ));
+            assert_eq!(room.replay.as_ref().unwrap().steps.len(), 0);
+
+            // but the server can
+            room.game_action(&current, timeout).unwrap();
+            assert_eq!(room.replay.as_ref().unwrap().steps.len(), 1);
+        });
+    }
+
+    #[test]
+    fn kicked_players_wait_for_the_next_game() {
+        System::new("test").block_on(async {
+            let waiting_room = WaitingRoom::new(
+                Duration::from_secs(60),
+                Duration::from_secs(60)
+            ).start();
+            let type_ = GameType::get("test_game").unwrap();
+            let mut room = GameRoom::new("room", type_, None, waiting_room);
+            for player in ["a", "b", "c"] {
+                room.add_player(player);
+            }
+            room.host = Some("a".to_string());
+            let host = Uuid::new_v4();
+            room.client_players.insert(host, "a".to_string());
+            let room = room.start();
+            let request = |uuid, action| room.send(GameRoomRequest {
+                uuid,
+                action: GameAction(action),
+            });
+
+            request(host, serde_json::json!({
+                "action": "kick",
+                "name": "b",
+            })).await.unwrap().unwrap();
+            let rejoin = || request(Uuid::new_v4(), serde_json::json!({
+                "action": "join_game",
+                "name": "b",
+            }));
+            assert!(matches!(rejoin().await.unwrap(), Err(Error::Kicked)));
+
+            // until the next game
+            request(host, serde_json::json!({
+                "action": "start_game",
+            })).await.unwrap().unwrap();
+            rejoin().await.unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn away_hosts_are_replaced() {
+        System::new("test").block_on(async {
+            let mut room = room_in_game(&["a", "b", "c"]);
+            room.host_grace_period = Some(Duration::from_secs(0));
+            room.bots.insert("b".to_string(), BotKind::Random);
+            room.client_players.insert(Uuid::new_v4(), "b".to_string());
+
+            // no humans around to take over
+            room.check_host_away();
+            assert_eq!(room.host.as_deref(), Some("a"));
+            assert!(room.host_away_since.is_some());
+
+            // someone shows up
+            room.client_players.insert(Uuid::new_v4(), "c".to_string());
+            room.check_host_away();
+            assert_eq!(room.host.as_deref(), Some("c"));
+            assert!(room.host_away_since.is_none());
+        });
+    }
+
+    #[test]
+    fn hosts_get_a_grace_period() {
+        System::new("test").block_on(async {
+            let mut room = room_in_game(&["a", "b"]);
+            room.host_grace_period = Some(Duration::from_secs(60));
+            room.client_players.insert(Uuid::new_v4(), "b".to_string());
+
+            room.check_host_away();
+            assert_eq!(room.host.as_deref(), Some("a"));
+            assert!(room.host_away_since.is_some());
+
+            // and coming back resets it
+            room.client_players.insert(Uuid::new_v4(), "a".to_string());
+            room.check_host_away();
+            assert_eq!(room.host.as_deref(), Some("a"));
+            assert!(room.host_away_since.is_none());
+        });
+    }
+
+    #[test]
+    fn clients_cant_destroy_rooms() {
+        let action = |action| serde_json::from_value::<WaitingRoomAction>(action);
+        assert!(action(serde_json::json!({
+            "action": "create_room",
+            "room_name": "r1",
+            "room_type": "test_game",
+            "host": "alice",
+        })).is_ok());
+        assert!(action(serde_json::json!({
+            "action": "destroy_room",
+            "room_name": "r1",
+        })).is_err());
+    }
+
+    #[test]
+    fn restored_rooms_keep_their_saves() {
+        System::new("test").block_on(async {
+            let dir = std::env::temp_dir()
+                .join(format!("ll-server-restore-{}", std::process::id()));
+            let store: Arc<dyn Store> = Arc::new(FileStore::new(dir.clone()).unwrap());
+            let type_ = GameType::get("test_game").unwrap();
+            let create_room = || WaitingRoomAction::CreateRoom {
+                room_name: "r1".to_string(),
+                room_type: type_.clone(),
+                host: "a".to_string(),
+                options: serde_json::Value::Null,
+                turn_limit: None,
+                password: None,
+                private: false,
+            };
+            let reap = || actix::clock::delay_for(Duration::from_millis(100));
+            let start = |restore_grace_period| {
+                let mut waiting_room = WaitingRoom::new(
+                    Duration::from_millis(10),
+                    Duration::from_secs(0)
+                );
+                waiting_room.restore_grace_period = restore_grace_period;
+                waiting_room.start()
+            };
+
+            let waiting_room = start(Duration::from_secs(60));
+            GameRoom::new("r1", type_.clone(), Some(store.clone()), waiting_room)
+                .save().unwrap();
+
+            // restored rooms outlast the usual grace period
+            let waiting_room = start(Duration::from_secs(60));
+            waiting_room.send(WaitingRoomRestore(store.clone())).await
+                .unwrap().unwrap();
+            reap().await;
+            assert!(matches!(
+                waiting_room.send(create_room()).await.unwrap(),
+                Err(Error::RoomExists(_))
+            ));
+
+            // and even once they're gone, the save stays
+            let waiting_room = start(Duration::from_secs(0));
+            waiting_room.send(WaitingRoomRestore(store.clone())).await
+                .unwrap().unwrap();
+            reap().await;
+            assert_eq!(store.load().unwrap().len(), 1);
+
+            // unlike rooms nobody ever restored
+            waiting_room.send(create_room()).await.unwrap().unwrap();
+            reap().await;
+            assert_eq!(store.load().unwrap().len(), 0);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        });
+    }
+}
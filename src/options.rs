@@ -0,0 +1,241 @@
+//! game options
+//!
+//! Each game describes its options with a JSON schema, which the
+//! waiting room uses to render a form, and which we check options
+//! against when creating a room. We only understand the small subset
+//! of JSON schema games actually use:
+//!
+//! - the top level is an object with properties, and nothing else is
+//!   allowed in it
+//! - properties are booleans, integers (with optional minimum and
+//!   maximum), or strings (with an optional enum)
+//! - properties may have a default, which is filled in if missing
+//! - title and description are for humans, and ignored here
+
+use crate::error::*;
+use serde_json::Value;
+
+/// schema for games without any options
+pub fn no_options() -> Value {
+    serde_json::json!({
+        "type": "object",
+        "properties": {},
+        "additionalProperties": false,
+    })
+}
+
+/// check options against a schema, returning the options with any
+/// defaults filled in
+pub fn validate(schema: &Value, options: Value) -> Result<Value, Error> {
+    // no options means all defaults
+    let mut options = match options {
+        Value::Null => serde_json::Map::new(),
+        Value::Object(options) => options,
+        _ => Err(Error::InvalidOptions(
+            "options must be an object".to_string()))?,
+    };
+
+    let properties = schema["properties"].as_object()
+        .ok_or_else(|| Error::Internal(
+            "options schema without properties?".to_string()))?;
+
+    // nothing we don't know about
+    if let Some(name) = options.keys()
+            .find(|name| !properties.contains_key(*name)) {
+        Err(Error::InvalidOptions(format!("unknown option {:?}", name)))?;
+    }
+
+    for (name, property) in properties.iter() {
+        match options.get(name) {
+            Some(value) => validate_property(name, property, value)?,
+            None => {
+                if let Some(default) = property.get("default") {
+                    options.insert(name.clone(), default.clone());
+                }
+            }
+        }
+    }
+
+    Ok(Value::Object(options))
+}
+
+fn validate_property(
+    name: &str,
+    property: &Value,
+    value: &Value,
+) -> Result<(), Error> {
+    match property["type"].as_str() {
+        Some("boolean") => {
+            if !value.is_boolean() {
+                Err(Error::InvalidOptions(format!(
+                    "{:?} must be true or false", name)))?;
+            }
+        }
+        Some("integer") => {
+            let value = value.as_i64()
+                .ok_or_else(|| Error::InvalidOptions(format!(
+                    "{:?} must be an integer", name)))?;
+            if let Some(minimum) = property["minimum"].as_i64() {
+                if value < minimum {
+                    Err(Error::InvalidOptions(format!(
+                        "{:?} must be at least {}", name, minimum)))?;
+                }
+            }
+            if let Some(maximum) = property["maximum"].as_i64() {
+                if value > maximum {
+                    Err(Error::InvalidOptions(format!(
+                        "{:?} must be at most {}", name, maximum)))?;
+                }
+            }
+        }
+        Some("string") => {
+            if !value.is_string() {
+                Err(Error::InvalidOptions(format!(
+                    "{:?} must be a string", name)))?;
+            }
+            if let Some(choices) = property["enum"].as_array() {
+                if !choices.contains(value) {
+                    Err(Error::InvalidOptions(format!(
+                        "{:?} must be one of {}",
+                        name, Value::Array(choices.clone()))))?;
+                }
+            }
+        }
+        _ => Err(Error::Internal(format!(
+            "option {:?} has a type we don't understand", name)))?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_game::TestGameOptions;
+
+    fn schema() -> Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "fast": {
+                    "type": "boolean",
+                    "default": false,
+                },
+                "rounds": {
+                    "type": "integer",
+                    "minimum": 1,
+                    "maximum": 9,
+                    "default": 3,
+                },
+                "deck": {
+                    "type": "string",
+                    "enum": ["classic", "premium"],
+                },
+                "motto": {
+                    "type": "string",
+                    "title": "Anything goes",
+                },
+            },
+            "additionalProperties": false,
+        })
+    }
+
+    #[test]
+    fn options_schema() {
+        let schema = TestGameOptions::schema();
+        let validate = |options| validate(&schema, options);
+
+        // nothing is required
+        assert_eq!(validate(Value::Null), Ok(serde_json::json!({})));
+        assert_eq!(
+            validate(serde_json::json!({"stabbies": 3})),
+            Ok(serde_json::json!({"stabbies": 3}))
+        );
+
+        for options in [
+            serde_json::json!([]),
+            serde_json::json!({"stabbies": -1}),
+            serde_json::json!({"stabbies": 41}),
+            serde_json::json!({"stabbies": "lots"}),
+            serde_json::json!({"princesses": 2}),
+        ] {
+            assert!(matches!(validate(options), Err(Error::InvalidOptions(_))));
+        }
+
+        // and whatever passes, the game understands
+        let options = validate(serde_json::json!({"protects": 2})).unwrap();
+        let options = serde_json::from_value::<TestGameOptions>(options).unwrap();
+        assert_eq!(options.protects, Some(2));
+        assert_eq!(options.stabbies, None);
+    }
+
+    #[test]
+    fn defaults_are_filled_in() {
+        let schema = schema();
+        assert_eq!(
+            validate(&schema, Value::Null),
+            Ok(serde_json::json!({"fast": false, "rounds": 3}))
+        );
+        // but never over what was given
+        assert_eq!(
+            validate(&schema, serde_json::json!({"fast": true, "rounds": 9})),
+            Ok(serde_json::json!({"fast": true, "rounds": 9}))
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        let schema = schema();
+        assert!(validate(&schema, serde_json::json!({"fast": true})).is_ok());
+        for fast in [
+            serde_json::json!(1),
+            serde_json::json!("true"),
+            Value::Null,
+        ] {
+            assert!(matches!(
+                validate(&schema, serde_json::json!({"fast": fast})),
+                Err(Error::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn strings() {
+        let schema = schema();
+        assert_eq!(
+            validate(&schema, serde_json::json!({"deck": "premium"})),
+            Ok(serde_json::json!({"deck": "premium", "fast": false, "rounds": 3}))
+        );
+        // without an enum anything goes, as long as it's a string
+        assert!(validate(&schema, serde_json::json!({"motto": "hi"})).is_ok());
+
+        for options in [
+            serde_json::json!({"deck": "deluxe"}),
+            serde_json::json!({"deck": 1}),
+            serde_json::json!({"motto": ["hi"]}),
+        ] {
+            assert!(matches!(
+                validate(&schema, options),
+                Err(Error::InvalidOptions(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn bad_schemas() {
+        // these are our fault, not the player's
+        assert!(matches!(
+            validate(&serde_json::json!({"type": "object"}), Value::Null),
+            Err(Error::Internal(_))
+        ));
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"when": {"type": "date"}},
+        });
+        assert!(matches!(
+            validate(&schema, serde_json::json!({"when": "now"})),
+            Err(Error::Internal(_))
+        ));
+        assert_eq!(validate(&no_options(), Value::Null), Ok(serde_json::json!({})));
+    }
+}
@@ -0,0 +1,65 @@
+//! room passwords
+//!
+//! We only ever need to check passwords, so we keep a salted hash
+//! instead of the password itself. That way snapshots and anything
+//! else that ends up on disk don't give passwords away.
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordHash {
+    salt: String,
+    hash: String,
+}
+
+impl PasswordHash {
+    pub fn new(password: &str) -> PasswordHash {
+        let salt = hex(&rand::thread_rng().gen::<[u8; 16]>());
+        let hash = hash(&salt, password);
+        PasswordHash {
+            salt,
+            hash,
+        }
+    }
+
+    pub fn check(&self, password: &str) -> bool {
+        hash(&self.salt, password) == self.hash
+    }
+}
+
+fn hash(salt: &str, password: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(password.as_bytes());
+    hex(&hasher.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_check() {
+        let hash = PasswordHash::new("hunter2");
+        assert!(hash.check("hunter2"));
+        assert!(!hash.check("hunter3"));
+        assert!(!hash.check(""));
+
+        // same password, different salt
+        let other = PasswordHash::new("hunter2");
+        assert_ne!(hash, other);
+        assert!(other.check("hunter2"));
+
+        // and nothing of the password is saved
+        let saved = serde_json::to_string(&hash).unwrap();
+        assert!(!saved.contains("hunter2"));
+    }
+}
@@ -0,0 +1,321 @@
+//! the websocket protocol
+//!
+//! Clients pick a protocol version when connecting, with a query
+//! parameter on the websocket url:
+//!
+//!     /ws?version=1
+//!     /room/{room}/{user}/ws?version=1
+//!
+//! If we don't speak that version (or none is given), the socket is
+//! closed right away with close code 4000 and a reason listing the
+//! versions we do speak.
+//!
+//! Rooms with a password also need ?password=..., without it the
+//! upgrade fails with a 403.
+//!
+//! Every message, in both directions, is wrapped in an envelope:
+//!
+//!     {"type": "join_game", "version": 1, "seq": 1, "payload": {...}}
+//!
+//! - type: what the message is, for client messages this is the action
+//! - version: the negotiated version, anything else is rejected
+//! - seq: starts at 1 and counts up, each side numbers its own messages,
+//!   so a gap means something went missing
+//! - payload: the message itself, always an object
+//!
+//! Version 1 client messages, anything else is rejected with an error:
+//!
+//! - waiting room: create_room, which takes the host's name and an
+//!   options object which is checked against the game's options
+//!   schema, rooms are only destroyed by the server once they've been
+//!   empty for a while
+//! - game room: join_game, spectate, leave_game, start_game, add_bot,
+//!   and whatever actions the game itself understands
+//! - game room, host only: kick, ban, transfer_host, lock_room,
+//!   kicked players can come back once the next game starts, banned
+//!   players can't
+//! - game room, delta mode only: ack {seq}, resync
+//!
+//! Version 1 server messages:
+//!
+//! - waiting room: rooms {rooms} when connecting, then room_created
+//!   {room_name, room}, room_updated {room_name, room} and room_destroyed
+//!   {room_name} as things change, and room_hosting {room_name, name,
+//!   token} only to whoever created a room, the token is for joining
+//!   it as the host
+//! - game room: state, the full room state, or with ?delta, a snapshot
+//!   to start with and then patch {base, patch}, an RFC 6902 patch
+//!   against the state with seq base
+//! - both: error {error: {code, message}, request_id, reply_to}, only
+//!   sent to the client that caused it, reply_to is the seq of the
+//!   message that caused it
+//!
+//! Messages without a seq or request_id, such as errors for messages
+//! we couldn't parse, have these set to null.
+
+use crate::error::*;
+use actix::prelude::*;
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// versions we speak, newest last
+pub const PROTOCOL_VERSIONS: &[u32] = &[1];
+
+/// close code for clients asking for a version we don't speak
+pub const CLOSE_UNSUPPORTED_VERSION: u16 = 4000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(rename="type")]
+    pub type_: String,
+    pub version: u32,
+    pub seq: u64,
+    #[serde(default)]
+    pub payload: Value,
+}
+
+impl Envelope {
+    /// unwrap into the action-tagged message the rest of the server
+    /// understands
+    pub fn into_action(self) -> Result<Value, Error> {
+        let mut payload = match self.payload {
+            Value::Null => serde_json::Map::new(),
+            Value::Object(payload) => payload,
+            _ => Err(Error::BadMessage(
+                "payload must be an object".to_string()))?,
+        };
+
+        payload.insert("action".to_string(), Value::String(self.type_));
+        Ok(Value::Object(payload))
+    }
+}
+
+/// our end of a websocket, keeps track of sequence numbers
+#[derive(Debug)]
+pub struct Connection {
+    pub version: u32,
+    // last seq we sent
+    seq: u64,
+    // last seq we got
+    peer_seq: u64,
+}
+
+impl Connection {
+    /// pick a version from the websocket url's query string
+    pub fn negotiate(query: &str) -> Result<Self, Error> {
+        let version = query.split('&')
+            .find_map(|param| param.strip_prefix("version="));
+
+        match version.and_then(|version| version.parse::<u32>().ok()) {
+            Some(version) if PROTOCOL_VERSIONS.contains(&version) => {
+                Ok(Self {
+                    version,
+                    seq: 0,
+                    peer_seq: 0,
+                })
+            }
+            _ => Err(Error::UnsupportedVersion(
+                version.unwrap_or("none").to_string()
+            )),
+        }
+    }
+
+    /// wrap up a message, giving it the next seq
+    pub fn wrap(&mut self, type_: &str, payload: Value) -> Envelope {
+        self.seq += 1;
+        Envelope {
+            type_: type_.to_string(),
+            version: self.version,
+            seq: self.seq,
+            payload,
+        }
+    }
+
+    /// wrap up a message that's already tagged with type/payload
+    pub fn wrap_message<T: Serialize>(
+        &mut self,
+        msg: &T
+    ) -> Result<Envelope, Error> {
+        let mut msg = serde_json::to_value(msg)
+            .map_err(|err| Error::Internal(err.to_string()))?;
+        let type_ = msg["type"].as_str()
+            .ok_or_else(|| Error::Internal("untyped message".to_string()))?
+            .to_string();
+        Ok(self.wrap(&type_, msg["payload"].take()))
+    }
+
+    /// parse an incoming message, checking it's for our version and
+    /// in order
+    pub fn unwrap(&mut self, text: &str) -> Result<Envelope, Error> {
+        let envelope = serde_json::from_str::<Envelope>(text)?;
+        if envelope.version != self.version {
+            Err(Error::BadMessage(format!(
+                "expected protocol version {}, got {}",
+                self.version, envelope.version)))?;
+        }
+
+        if envelope.seq <= self.peer_seq {
+            Err(Error::BadMessage(format!(
+                "expected seq after {}, got {}",
+                self.peer_seq, envelope.seq)))?;
+        }
+
+        self.peer_seq = envelope.seq;
+        Ok(envelope)
+    }
+}
+
+/// websocket for clients we can't talk to, closes right away
+#[derive(Debug)]
+pub struct Rejected(pub Error);
+
+impl Actor for Rejected {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Other(CLOSE_UNSUPPORTED_VERSION),
+            description: Some(self.0.to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Rejected {
+    fn handle(
+        &mut self,
+        _msg: Result<ws::Message, ws::ProtocolError>,
+        _ctx: &mut Self::Context,
+    ) {
+        // not listening
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(version: u32, seq: u64, payload: Value) -> String {
+        serde_json::json!({
+            "type": "join_game",
+            "version": version,
+            "seq": seq,
+            "payload": payload,
+        }).to_string()
+    }
+
+    #[test]
+    fn negotiate_versions() {
+        let conn = Connection::negotiate("version=1").unwrap();
+        assert_eq!(conn.version, 1);
+        // wherever it is in the query
+        let conn = Connection::negotiate("delta&version=1&password=x").unwrap();
+        assert_eq!(conn.version, 1);
+
+        assert_eq!(
+            Connection::negotiate("").unwrap_err(),
+            Error::UnsupportedVersion("none".to_string())
+        );
+        assert_eq!(
+            Connection::negotiate("delta").unwrap_err(),
+            Error::UnsupportedVersion("none".to_string())
+        );
+        assert_eq!(
+            Connection::negotiate("version=2").unwrap_err(),
+            Error::UnsupportedVersion("2".to_string())
+        );
+        assert_eq!(
+            Connection::negotiate("version=one").unwrap_err(),
+            Error::UnsupportedVersion("one".to_string())
+        );
+    }
+
+    #[test]
+    fn unwrap_checks_version() {
+        let mut conn = Connection::negotiate("version=1").unwrap();
+        assert!(matches!(
+            conn.unwrap(&message(2, 1, serde_json::json!({}))),
+            Err(Error::BadMessage(_))
+        ));
+        // and missing versions don't parse at all
+        assert!(matches!(
+            conn.unwrap(r#"{"type": "join_game", "seq": 1, "payload": {}}"#),
+            Err(Error::BadMessage(_))
+        ));
+        conn.unwrap(&message(1, 1, serde_json::json!({}))).unwrap();
+    }
+
+    #[test]
+    fn unwrap_checks_seq() {
+        let mut conn = Connection::negotiate("version=1").unwrap();
+        conn.unwrap(&message(1, 1, serde_json::json!({}))).unwrap();
+
+        // repeated
+        assert!(matches!(
+            conn.unwrap(&message(1, 1, serde_json::json!({}))),
+            Err(Error::BadMessage(_))
+        ));
+        // gaps are fine, something just went missing
+        conn.unwrap(&message(1, 5, serde_json::json!({}))).unwrap();
+        // out of order
+        assert!(matches!(
+            conn.unwrap(&message(1, 3, serde_json::json!({}))),
+            Err(Error::BadMessage(_))
+        ));
+        conn.unwrap(&message(1, 6, serde_json::json!({}))).unwrap();
+    }
+
+    #[test]
+    fn type_becomes_action() {
+        let mut conn = Connection::negotiate("version=1").unwrap();
+        let action = conn.unwrap(&message(1, 1, serde_json::json!({
+                "name": "alice",
+                // the type wins
+                "action": "start_game",
+            })))
+            .and_then(|envelope| envelope.into_action())
+            .unwrap();
+        assert_eq!(action, serde_json::json!({
+            "action": "join_game",
+            "name": "alice",
+        }));
+
+        // no payload is an empty one
+        let action = conn.unwrap(r#"{"type": "resync", "version": 1, "seq": 2}"#)
+            .and_then(|envelope| envelope.into_action())
+            .unwrap();
+        assert_eq!(action, serde_json::json!({"action": "resync"}));
+    }
+
+    #[test]
+    fn payload_must_be_an_object() {
+        let mut conn = Connection::negotiate("version=1").unwrap();
+        for (seq, payload) in [
+            serde_json::json!([1, 2]),
+            serde_json::json!("join_game"),
+            serde_json::json!(3),
+        ].iter().enumerate() {
+            let envelope = conn.unwrap(&message(1, seq as u64 + 1, payload.clone()))
+                .unwrap();
+            assert!(matches!(
+                envelope.into_action(),
+                Err(Error::BadMessage(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn wrap_counts_up() {
+        let mut conn = Connection::negotiate("version=1").unwrap();
+        let first = conn.wrap("state", serde_json::json!({}));
+        let second = conn.wrap_message(&serde_json::json!({
+            "type": "room_destroyed",
+            "payload": {"room_name": "r"},
+        })).unwrap();
+        assert_eq!((first.seq, second.seq), (1, 2));
+        assert_eq!(second.type_, "room_destroyed");
+        assert_eq!(second.version, 1);
+        assert_eq!(second.payload, serde_json::json!({"room_name": "r"}));
+    }
+}
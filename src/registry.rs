@@ -0,0 +1,267 @@
+//! the games we know how to play
+//!
+//! Every game is described by a factory, which knows how to create and
+//! load games, what bots can play them, and what the waiting room shows
+//! about them. Rooms, snapshots and replays only refer to games by id,
+//! so the registry is what ties these back to actual code.
+
+use crate::bot::*;
+use crate::error::*;
+use crate::game::*;
+use crate::love_letter::*;
+use crate::options;
+use crate::test_game::*;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+pub trait GameFactory: Send + Sync + fmt::Debug {
+    // unique id, used to refer to the game everywhere
+    fn id(&self) -> &str;
+
+    // for humans
+    fn name(&self) -> &str;
+    fn description(&self) -> &str;
+
+    // fewest players a game can start with
+    fn min_players(&self) -> usize;
+
+    // most players a room can hold
+    fn max_players(&self) -> usize;
+
+    // static files the game's states refer to, relative to the
+    // server's root
+    fn assets(&self) -> Vec<String> {
+        vec![]
+    }
+
+    // JSON schema for the options picked when creating a room
+    fn options_schema(&self) -> Value {
+        options::no_options()
+    }
+
+    // create a new game, options have already been checked against
+    // the schema, with defaults filled in
+    fn create(
+        &self,
+        players: Vec<String>,
+        seed: u64,
+        options: Value,
+    ) -> Result<Box<dyn Game>, Error>;
+
+    // restore a game from what Game::save returned
+    fn load(&self, state: Value) -> Result<Box<dyn Game>, Error>;
+
+    // a bot that can play this game, if we have one
+    fn bot(&self, kind: BotKind) -> Result<Box<dyn Bot>, Error> {
+        Err(Error::NoSuchBot(format!("{:?}", kind).to_lowercase()))
+    }
+}
+
+/// every game that's built in, add new games here
+fn builtin() -> Vec<Arc<dyn GameFactory>> {
+    vec![
+        Arc::new(TestGameFactory),
+        Arc::new(LoveLetterFactory),
+    ]
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Vec<Arc<dyn GameFactory>>> =
+        RwLock::new(builtin());
+}
+
+/// add a game at runtime, ids must be unique
+pub fn register(factory: Arc<dyn GameFactory>) -> Result<(), Error> {
+    let mut registry = REGISTRY.write().unwrap();
+    if registry.iter().any(|other| other.id() == factory.id()) {
+        Err(Error::Internal(format!(
+            "game {:?} is already registered", factory.id())))?;
+    }
+
+    registry.push(factory);
+    Ok(())
+}
+
+/// a registered game, serialized as its id
+#[derive(Clone)]
+pub struct GameType(Arc<dyn GameFactory>);
+
+impl GameType {
+    pub fn get(id: &str) -> Result<GameType, Error> {
+        REGISTRY.read().unwrap().iter()
+            .find(|factory| factory.id() == id)
+            .map(|factory| GameType(factory.clone()))
+            .ok_or_else(|| Error::NoSuchGame(id.to_string()))
+    }
+
+    /// every registered game, in the order they were registered
+    pub fn all() -> Vec<GameType> {
+        REGISTRY.read().unwrap().iter()
+            .map(|factory| GameType(factory.clone()))
+            .collect()
+    }
+
+    pub fn min_players(&self) -> usize {
+        self.0.min_players()
+    }
+
+    pub fn max_players(&self) -> usize {
+        self.0.max_players()
+    }
+
+    /// check options against the game's schema, filling in defaults
+    pub fn options(&self, options: Value) -> Result<Value, Error> {
+        options::validate(&self.0.options_schema(), options)
+    }
+
+    /// create a game, checking the options first, saved options may
+    /// be from before the schema changed
+    pub fn create(
+        &self,
+        players: Vec<String>,
+        seed: u64,
+        options: &Value,
+    ) -> Result<Box<dyn Game>, Error> {
+        let options = self.options(options.clone())?;
+        self.0.create(players, seed, options)
+    }
+
+    pub fn load(&self, state: Value) -> Result<Box<dyn Game>, Error> {
+        self.0.load(state)
+    }
+
+    pub fn bot(&self, kind: BotKind) -> Result<Box<dyn Bot>, Error> {
+        self.0.bot(kind)
+    }
+
+    /// the kinds of bots that can play this game, if any
+    pub fn bot_kinds(&self) -> Vec<BotKind> {
+        BotKind::all().iter()
+            .filter(|kind| self.0.bot(**kind).is_ok())
+            .copied()
+            .collect()
+    }
+
+    /// everything the waiting room needs to know about a game
+    pub fn info(&self) -> Value {
+        serde_json::json!({
+            "id": self.0.id(),
+            "name": self.0.name(),
+            "description": self.0.description(),
+            "min_players": self.0.min_players(),
+            "max_players": self.0.max_players(),
+            "assets": self.0.assets(),
+            "options": self.0.options_schema(),
+        })
+    }
+}
+
+impl fmt::Debug for GameType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.0.id())
+    }
+}
+
+impl PartialEq for GameType {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.id() == other.0.id()
+    }
+}
+
+impl Serialize for GameType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for GameType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        GameType::get(&id).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a game that only exists to be registered
+    #[derive(Debug)]
+    struct NoGameFactory(&'static str);
+
+    impl GameFactory for NoGameFactory {
+        fn id(&self) -> &str { self.0 }
+        fn name(&self) -> &str { "No game" }
+        fn description(&self) -> &str { "" }
+        fn min_players(&self) -> usize { 1 }
+        fn max_players(&self) -> usize { 1 }
+
+        fn create(
+            &self,
+            _players: Vec<String>,
+            _seed: u64,
+            _options: Value,
+        ) -> Result<Box<dyn Game>, Error> {
+            Err(Error::InvalidAction("not a game".to_string()))
+        }
+
+        fn load(&self, _state: Value) -> Result<Box<dyn Game>, Error> {
+            Err(Error::InvalidAction("not a game".to_string()))
+        }
+    }
+
+    #[test]
+    fn registered() {
+        let type_ = GameType::get("test_game").unwrap();
+        assert_eq!(serde_json::to_value(&type_).unwrap(), "test_game");
+        assert_eq!(
+            serde_json::from_value::<GameType>(serde_json::json!("test_game")).unwrap(),
+            type_
+        );
+        assert!(matches!(GameType::get("no_game"), Err(Error::NoSuchGame(_))));
+
+        let info = type_.info();
+        assert_eq!(info["min_players"], 2);
+        assert_eq!(info["max_players"], 6);
+        assert_eq!(info["options"], TestGameOptions::schema());
+
+        // the room goes through here to make games
+        let game = type_.create(
+            vec![format!("p0"), format!("p1")],
+            0,
+            &serde_json::json!({"protects": 0})
+        ).unwrap();
+        let state = game.state_for(None).0;
+        assert_eq!(state["players"].as_array().unwrap().len(), 2);
+        assert!(type_.bot(BotKind::Heuristic).is_ok());
+        assert_eq!(type_.bot_kinds(), vec![BotKind::Random, BotKind::Heuristic]);
+    }
+
+    #[test]
+    fn ids_are_unique() {
+        register(Arc::new(NoGameFactory("registry_test"))).unwrap();
+        let type_ = GameType::get("registry_test").unwrap();
+        assert_eq!(type_.info()["name"], "No game");
+        assert!(type_.bot(BotKind::Random).is_err());
+        assert_eq!(type_.bot_kinds(), vec![]);
+
+        // not again, and not over a builtin either
+        assert!(matches!(
+            register(Arc::new(NoGameFactory("registry_test"))),
+            Err(Error::Internal(_))
+        ));
+        assert!(matches!(
+            register(Arc::new(TestGameFactory)),
+            Err(Error::Internal(_))
+        ));
+        assert_eq!(
+            GameType::all().iter()
+                .filter(|type_| type_.info()["id"] == "registry_test")
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! persistent storage for rooms, so games survive restarts
+
+use crate::error::*;
+use log::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub trait Store: Send + Sync + std::fmt::Debug {
+    // save a room's snapshot, replacing any older snapshot
+    fn save(
+        &self,
+        room_name: &str,
+        snapshot: &serde_json::Value,
+    ) -> Result<(), Error>;
+
+    // forget about a room
+    fn remove(&self, room_name: &str) -> Result<(), Error>;
+
+    // load all saved rooms, skipping any that can't be read
+    fn load(&self) -> Result<Vec<(String, serde_json::Value)>, Error>;
+}
+
+/// stores each room as a json file in a directory
+#[derive(Debug)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+        })
+    }
+
+    fn path(&self, room_name: &str) -> PathBuf {
+        // room names can be anything, so escape anything that
+        // could confuse the filesystem
+        let mut name = String::new();
+        for b in room_name.bytes() {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+                name.push(b as char);
+            } else {
+                name.push_str(&format!("%{:02x}", b));
+            }
+        }
+
+        self.dir.join(format!("{}.json", name))
+    }
+
+    fn load_file(path: &Path) -> Result<(String, serde_json::Value), Error> {
+        let mut saved = serde_json::from_slice::<serde_json::Value>(
+            &fs::read(path)?
+        )?;
+        let room_name = saved["room_name"].as_str()
+            .ok_or_else(|| Error::Internal(format!(
+                "no room name in {:?}?", path)))?
+            .to_string();
+        Ok((room_name, saved["room"].take()))
+    }
+}
+
+impl Store for FileStore {
+    fn save(
+        &self,
+        room_name: &str,
+        snapshot: &serde_json::Value,
+    ) -> Result<(), Error> {
+        // write to a temporary file first so we never leave a
+        // half-written snapshot around
+        let path = self.path(room_name);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&serde_json::json!({
+            "room_name": room_name,
+            "room": snapshot,
+        }))?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn remove(&self, room_name: &str) -> Result<(), Error> {
+        let path = self.path(room_name);
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Vec<(String, serde_json::Value)>, Error> {
+        let mut rooms = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+
+            // one bad file shouldn't take every other room with it
+            match FileStore::load_file(&path) {
+                Ok(room) => rooms.push(room),
+                Err(err) => warn!("can't load {:?}: {}", path, err),
+            }
+        }
+
+        Ok(rooms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_files_are_skipped() {
+        let dir = std::env::temp_dir()
+            .join(format!("ll-server-store-{}", std::process::id()));
+        let store = FileStore::new(dir.clone()).unwrap();
+        store.save("a room/1", &serde_json::json!({"x": 1})).unwrap();
+        fs::write(dir.join("garbage.json"), b"{not json").unwrap();
+        fs::write(dir.join("nameless.json"), b"{\"room\": {}}").unwrap();
+        fs::write(dir.join("notes.txt"), b"not a room").unwrap();
+
+        let rooms = store.load().unwrap();
+        assert_eq!(rooms, vec![
+            ("a room/1".to_string(), serde_json::json!({"x": 1})),
+        ]);
+
+        store.remove("a room/1").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! delta sync
+//!
+//! Clients in delta mode get JSON patches against the last state they
+//! acked instead of the full state every time. We keep every state they
+//! haven't acked yet, so whichever one they ack next can be the base for
+//! the patches after it.
+
+use crate::protocol::*;
+use serde_json::Value;
+use std::collections::VecDeque;
+
+/// how many states a delta client can leave unacked before we give up
+/// and send them a full snapshot
+pub const MAX_UNACKED: usize = 32;
+
+#[derive(Debug, Default)]
+pub struct DeltaSync {
+    // last state we sent, for resyncs
+    latest: Option<Value>,
+    // last state the client acked, and the ones they haven't yet
+    acked: Option<(u64, Value)>,
+    unacked: VecDeque<(u64, Value)>,
+}
+
+impl DeltaSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// wrap up a state for the client, a patch if they've acked
+    /// something recent enough, otherwise a full snapshot
+    pub fn sync(&mut self, conn: &mut Connection, state: Value) -> Envelope {
+        if self.unacked.len() >= MAX_UNACKED {
+            self.acked = None;
+            self.unacked.clear();
+        }
+
+        let envelope = match &self.acked {
+            Some((base, acked)) => conn.wrap("patch", serde_json::json!({
+                "base": base,
+                "patch": json_patch::diff(acked, &state),
+            })),
+            None => conn.wrap("snapshot", state.clone()),
+        };
+
+        self.unacked.push_back((envelope.seq, state.clone()));
+        self.latest = Some(state);
+        envelope
+    }
+
+    /// the client got the state with this seq, diff against it from
+    /// now on, acks for states we aren't waiting on are ignored
+    pub fn ack(&mut self, seq: u64) {
+        if !self.unacked.iter().any(|(next, _)| *next == seq) {
+            return;
+        }
+
+        // anything older than this is no use anymore
+        while let Some((next, state)) = self.unacked.pop_front() {
+            if next == seq {
+                self.acked = Some((next, state));
+                break;
+            }
+        }
+    }
+
+    /// the client lost track, start over with a snapshot of the latest
+    /// state, if we haven't sent anything yet the first state will be a
+    /// snapshot anyway
+    pub fn resync(&mut self, conn: &mut Connection) -> Option<Envelope> {
+        self.acked = None;
+        self.unacked.clear();
+        let latest = self.latest.take()?;
+        Some(self.sync(conn, latest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn() -> Connection {
+        Connection::negotiate("version=1").unwrap()
+    }
+
+    fn state(turn: u64) -> Value {
+        serde_json::json!({"players": ["a", "b"], "turn": turn})
+    }
+
+    // apply a patch envelope to the state it's based on
+    fn apply(base: &Value, envelope: &Envelope) -> Value {
+        let patch = serde_json::from_value::<json_patch::Patch>(
+            envelope.payload["patch"].clone()
+        ).unwrap();
+        let mut state = base.clone();
+        json_patch::patch(&mut state, &patch).unwrap();
+        state
+    }
+
+    #[test]
+    fn snapshot_then_patches() {
+        let mut conn = conn();
+        let mut sync = DeltaSync::new();
+
+        let first = sync.sync(&mut conn, state(1));
+        assert_eq!(first.type_, "snapshot");
+        assert_eq!(first.payload, state(1));
+
+        // nothing acked yet, so still snapshots
+        let second = sync.sync(&mut conn, state(2));
+        assert_eq!(second.type_, "snapshot");
+
+        // patches are against whatever was acked
+        sync.ack(first.seq);
+        let third = sync.sync(&mut conn, state(3));
+        assert_eq!(third.type_, "patch");
+        assert_eq!(third.payload["base"], first.seq);
+        assert_eq!(apply(&state(1), &third), state(3));
+
+        sync.ack(third.seq);
+        let fourth = sync.sync(&mut conn, state(4));
+        assert_eq!(fourth.payload["base"], third.seq);
+        assert_eq!(apply(&state(3), &fourth), state(4));
+    }
+
+    #[test]
+    fn stale_acks_are_ignored() {
+        let mut conn = conn();
+        let mut sync = DeltaSync::new();
+
+        let first = sync.sync(&mut conn, state(1));
+        let second = sync.sync(&mut conn, state(2));
+        sync.ack(second.seq);
+
+        // too old, and never sent
+        sync.ack(first.seq);
+        sync.ack(second.seq + 100);
+        let third = sync.sync(&mut conn, state(3));
+        assert_eq!(third.payload["base"], second.seq);
+        assert_eq!(apply(&state(2), &third), state(3));
+    }
+
+    #[test]
+    fn too_many_unacked() {
+        let mut conn = conn();
+        let mut sync = DeltaSync::new();
+
+        let first = sync.sync(&mut conn, state(0));
+        sync.ack(first.seq);
+        for turn in 1..=MAX_UNACKED as u64 {
+            assert_eq!(sync.sync(&mut conn, state(turn)).type_, "patch");
+        }
+
+        // they've stopped listening, so start over
+        let envelope = sync.sync(&mut conn, state(100));
+        assert_eq!(envelope.type_, "snapshot");
+        assert_eq!(envelope.payload, state(100));
+    }
+
+    #[test]
+    fn resync() {
+        let mut conn = conn();
+        let mut sync = DeltaSync::new();
+
+        // nothing to resync yet
+        assert!(sync.resync(&mut conn).is_none());
+
+        let first = sync.sync(&mut conn, state(1));
+        sync.ack(first.seq);
+        sync.sync(&mut conn, state(2));
+
+        // the latest state, in full
+        let envelope = sync.resync(&mut conn).unwrap();
+        assert_eq!(envelope.type_, "snapshot");
+        assert_eq!(envelope.payload, state(2));
+
+        // even if everything was acked
+        sync.ack(envelope.seq);
+        let envelope = sync.resync(&mut conn).unwrap();
+        assert_eq!(envelope.type_, "snapshot");
+        assert_eq!(envelope.payload, state(2));
+
+        // and patches pick up from there once acked
+        sync.ack(envelope.seq);
+        let next = sync.sync(&mut conn, state(3));
+        assert_eq!(next.payload["base"], envelope.seq);
+        assert_eq!(apply(&state(2), &next), state(3));
+    }
+}
@@ -1,7 +1,12 @@
 //! test game
 
 use crate::game::*;
+use crate::error::*;
+use crate::bot::*;
+use crate::registry::*;
 use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use std::iter;
@@ -20,11 +25,14 @@ enum TestGameCard {
 enum TestGamePhase {
     BeforeTurn,
     Turn,
-    DecidingStabby,
+    // waiting for the current player to give a card back to target
+    DecidingStabby {
+        target: usize,
+    },
     Ended,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TestGame {
     players: Vec<String>,
     current: usize,
@@ -38,6 +46,101 @@ pub struct TestGame {
     log: Vec<String>,
 }
 
+/// options picked when creating a room
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all="snake_case")]
+pub struct TestGameOptions {
+    // otherwise depends on the number of players
+    #[serde(default)]
+    pub protects: Option<usize>,
+    #[serde(default)]
+    pub stabbies: Option<usize>,
+}
+
+impl TestGameOptions {
+    pub fn schema() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "protects": {
+                    "type": "integer",
+                    "title": "Protect cards",
+                    "description": "Defaults to half the players, rounded up",
+                    "minimum": 0,
+                    "maximum": 20,
+                },
+                "stabbies": {
+                    "type": "integer",
+                    "title": "Stabby cards",
+                    "description": "Defaults to two per player, plus two",
+                    "minimum": 0,
+                    "maximum": 40,
+                },
+            },
+            "additionalProperties": false,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TestGameFactory;
+
+impl GameFactory for TestGameFactory {
+    fn id(&self) -> &str {
+        "test_game"
+    }
+
+    fn name(&self) -> &str {
+        "Test game"
+    }
+
+    fn description(&self) -> &str {
+        "Find the princess and hang on to her until the deck runs out"
+    }
+
+    fn min_players(&self) -> usize {
+        2
+    }
+
+    fn max_players(&self) -> usize {
+        6
+    }
+
+    fn assets(&self) -> Vec<String> {
+        ["back", "princess", "protect", "stabby"].iter()
+            .map(|card| format!("test-card-{}.png", card))
+            .collect()
+    }
+
+    fn options_schema(&self) -> serde_json::Value {
+        TestGameOptions::schema()
+    }
+
+    fn create(
+        &self,
+        players: Vec<String>,
+        seed: u64,
+        options: serde_json::Value,
+    ) -> Result<Box<dyn Game>, Error> {
+        Ok(Box::new(TestGame::new(
+            players,
+            seed,
+            serde_json::from_value(options)?
+        )?))
+    }
+
+    fn load(&self, state: serde_json::Value) -> Result<Box<dyn Game>, Error> {
+        Ok(Box::new(TestGame::load(state)?))
+    }
+
+    fn bot(&self, kind: BotKind) -> Result<Box<dyn Bot>, Error> {
+        Ok(match kind {
+            BotKind::Random    => Box::new(RandomBot::new()),
+            BotKind::Heuristic => Box::new(TestGameHeuristicBot::new()),
+        })
+    }
+}
+
 // this is sort of a stub, we only allow drawing from the main deck
 #[derive(Debug, Serialize, Deserialize, Copy, Clone)]
 #[serde(rename_all="snake_case")]
@@ -57,25 +160,64 @@ enum TestGameAction {
         card: TestGameCard,
         target: String,
     },
+    // the server plays for players who take too long
+    Timeout {
+        user: String,
+    },
+}
+
+// shorthands for building actions
+fn draw_action(player: &str) -> GameAction {
+    GameAction(serde_json::json!({
+        "action": "draw",
+        "user": player,
+        "deck": "deck",
+    }))
+}
+
+fn play_action(player: &str, card: TestGameCard, target: &str) -> GameAction {
+    GameAction(serde_json::json!({
+        "action": "play",
+        "user": player,
+        "card": card,
+        "target": target,
+    }))
 }
 
 impl TestGame {
-    pub fn new(mut players: Vec<String>) -> TestGame {
+    pub fn new(
+        mut players: Vec<String>,
+        seed: u64,
+        options: TestGameOptions,
+    ) -> Result<TestGame, Error> {
+        // all randomness comes from the seed, so games can be replayed
+        let mut rng = StdRng::seed_from_u64(seed);
+
         // shuffle the player order!
-        players.shuffle(&mut rand::thread_rng());
+        players.shuffle(&mut rng);
         let current = 0;
 
         // create a deck, sort of arbitrary here
         // princess = 1
         // protect = 1/2 * players
         // stabby = 2 * players
+        let protects = options.protects.unwrap_or(players.len().div_ceil(2));
+        let stabbies = options.stabbies.unwrap_or((players.len()+1)*2);
         let mut deck = iter::once(TestGameCard::Princess)
-            .chain(iter::repeat(TestGameCard::Protect).take((players.len()+1)/2))
-            .chain(iter::repeat(TestGameCard::Stabby).take((players.len()+1)*2))
+            .chain(iter::repeat_n(TestGameCard::Protect, protects))
+            .chain(iter::repeat_n(TestGameCard::Stabby, stabbies))
             .collect::<Vec<_>>();
 
+        // everyone needs a card, and there needs to be something
+        // to draw
+        if deck.len() <= players.len() {
+            Err(Error::InvalidAction(format!(
+                "not enough cards for {} players, need at least {}",
+                players.len(), players.len()+1)))?;
+        }
+
         // of course
-        deck.shuffle(&mut rand::thread_rng());
+        deck.shuffle(&mut rng);
 
         // give each player one card at the start
         let down_hands = iter::repeat_with(|| {
@@ -85,44 +227,61 @@ impl TestGame {
             .collect::<Vec<_>>();
 
         // no active cards
-        let up_hands = iter::repeat(vec![])
-            .take(players.len())
+        let up_hands = iter::repeat_n(vec![], players.len())
             .collect::<Vec<_>>();
 
-        TestGame {
-            players: players,
-            current: current,
+        Ok(TestGame {
+            players,
+            current,
             phase: TestGamePhase::BeforeTurn,
-            down_hands: down_hands,
-            up_hands: up_hands,
-            deck: deck,
+            down_hands,
+            up_hands,
+            deck,
             discard: vec![],
             log: vec![
                 format!("Waiting for players..."),
                 format!("Shuffling..."),
                 format!("Game started"),
             ],
-        }
+        })
+    }
+
+    pub fn load(state: serde_json::Value) -> Result<TestGame, Error> {
+        Ok(serde_json::from_value(state)?)
     }
 
     fn find_player(
         &self,
         user: &str,
-    ) -> Result<usize, Box<dyn std::error::Error>> {
-        Ok(
-            self.players.iter().position(|p| p == user)
-                .ok_or_else(|| format!("player {:?} is not playing?", user))?
-        )
+    ) -> Result<usize, Error> {
+        self.players.iter().position(|p| p == user)
+            .ok_or_else(|| Error::NotPlaying(user.to_string()))
+    }
+
+    // discard a card without playing it, anything but the princess
+    fn discard(&mut self, user: usize) -> Result<(), Error> {
+        let i = self.down_hands[user].iter()
+            .position(|c| *c != TestGameCard::Princess)
+            .ok_or_else(|| Error::Internal("nothing to discard?".to_string()))?;
+        let card = self.down_hands[user].remove(i);
+        self.discard.push(card);
+
+        self.log.push(format!(
+            "{} discarded {:?}",
+            self.players[user],
+            card,
+        ));
+        Ok(())
     }
 
     fn end_turn(&mut self) {
         // end of game?
-        if self.deck.len() == 0 {
+        if self.deck.is_empty() {
             // resolve game!
             // who has the princess?
             let winner = self.down_hands.iter()
                 .position(|hand| {
-                    hand.iter().any(|c| *c == TestGameCard::Princess)
+                    hand.contains(&TestGameCard::Princess)
                 });
             match winner {
                 Some(winner) => {
@@ -136,8 +295,8 @@ impl TestGame {
                     ));
                 }
                 None => {
-                    self.log.push(format!("No one one??"));
-                    self.log.push(format!("How did you pull that off?"));
+                    self.log.push("No one one??".to_string());
+                    self.log.push("How did you pull that off?".to_string());
                 }
             }
 
@@ -158,27 +317,184 @@ impl TestGame {
 impl Game for TestGame {
     fn status(&self) -> String {
         match self.phase {
-            TestGamePhase::Ended => format!("ended"),
-            _ => format!("in game"),
+            TestGamePhase::Ended => "ended".to_string(),
+            _ => "in game".to_string(),
         }
     }
 
+    fn save(&self) -> Result<serde_json::Value, Error> {
+        Ok(serde_json::to_value(self)?)
+    }
+
     fn ended(&self) -> bool {
+        matches!(self.phase, TestGamePhase::Ended)
+    }
+
+    fn current_player(&self) -> Option<String> {
+        match self.phase {
+            TestGamePhase::Ended => None,
+            _ => Some(self.players[self.current].clone()),
+        }
+    }
+
+    fn legal_actions(&self, player: &str) -> Option<Vec<GameAction>> {
+        let user = match self.find_player(player) {
+            Ok(user) if user == self.current => user,
+            // not our turn, or not even playing
+            _ => return Some(vec![]),
+        };
+
+        let mut hand = self.down_hands[user].clone();
+        hand.dedup();
+        let mut actions = vec![];
+        match self.phase {
+            TestGamePhase::BeforeTurn => {
+                actions.push(draw_action(player));
+            }
+            TestGamePhase::Turn => {
+                for card in hand {
+                    for (target, name) in self.players.iter().enumerate() {
+                        let legal = match card {
+                            TestGameCard::Princess => false,
+                            TestGameCard::Protect => true,
+                            // stabbing someone protected does nothing
+                            TestGameCard::Stabby => {
+                                target == user
+                                    || !self.up_hands[target]
+                                        .contains(&TestGameCard::Protect)
+                            }
+                        };
+                        if legal {
+                            actions.push(play_action(player, card, name));
+                        }
+                    }
+                }
+            }
+            TestGamePhase::DecidingStabby{target} => {
+                for card in hand {
+                    actions.push(play_action(
+                        player,
+                        card,
+                        &self.players[target]
+                    ));
+                }
+            }
+            TestGamePhase::Ended => (),
+        }
+
+        Some(actions)
+    }
+
+    fn timeout_action(&self) -> Option<GameAction> {
+        let user = self.current_player()?;
+        Some(GameAction(serde_json::json!({
+            "action": TIMEOUT_ACTION,
+            "user": user,
+        })))
+    }
+
+    fn player_left(&mut self, player: &str) -> Result<(), Error> {
+        let user = self.find_player(player)?;
+        if self.ended() {
+            return Ok(());
+        }
+
+        self.log.push(format!("{} left the game", player));
+
+        // finish up any swap they were part of
+        let mut turn_over = user == self.current;
         match self.phase {
-            TestGamePhase::Ended => true,
-            _ => false,
+            TestGamePhase::DecidingStabby{target} if user == self.current => {
+                // give back whatever isn't the princess
+                let i = self.down_hands[user].iter()
+                    .position(|c| *c != TestGameCard::Princess)
+                    .unwrap_or(0);
+                let card = self.down_hands[user].remove(i);
+                self.down_hands[target].push(card);
+            }
+            TestGamePhase::DecidingStabby{target} if user == target => {
+                // no one to give a card back to
+                self.discard(self.current)?;
+                turn_over = true;
+            }
+            _ => (),
         }
+
+        // they forfeit, but their cards stay in play, hidden cards
+        // go to the bottom of the deck so the princess can still
+        // be drawn
+        let down_hand = self.down_hands.remove(user);
+        self.deck.splice(0..0, down_hand);
+        self.discard.extend(self.up_hands.remove(user));
+        self.players.remove(user);
+
+        // fix up indices
+        if user < self.current {
+            self.current -= 1;
+        }
+        if let TestGamePhase::DecidingStabby{target} = &mut self.phase {
+            if *target > user {
+                *target -= 1;
+            }
+        }
+
+        // last one standing?
+        if self.players.len() < 2 {
+            if let Some(winner) = self.players.first() {
+                self.log.push(format!("{} wins!", winner));
+            }
+            self.current = 0;
+            self.phase = TestGamePhase::Ended;
+            return Ok(());
+        }
+
+        if turn_over {
+            // pass the turn on as if the player before them had
+            // just finished
+            if user == self.current {
+                self.current = (user + self.players.len() - 1)
+                    % self.players.len();
+            }
+            self.end_turn();
+        }
+
+        Ok(())
     }
 
-    fn state(&self) -> GameState {
+    fn state_for(&self, player: Option<&str>) -> GameState {
         GameState(serde_json::json!({
             "players": self.players,
-            "current": self.players[self.current],
-            "phase": self.phase,
+            "current": self.players.get(self.current),
+            "phase": match self.phase {
+                TestGamePhase::BeforeTurn => "before_turn",
+                TestGamePhase::Turn => "turn",
+                TestGamePhase::DecidingStabby{..} => "deciding_stabby",
+                TestGamePhase::Ended => "ended",
+            },
+            // who still needs to decide a swap, and with who
+            "stabby": match self.phase {
+                TestGamePhase::DecidingStabby{target} => serde_json::json!({
+                    "user": self.players[self.current],
+                    "target": self.players[target],
+                }),
+                _ => serde_json::Value::Null,
+            },
             "down_hands": self.down_hands.iter()
                 .enumerate()
                 .map(|(i, hand)| {
-                    (&self.players[i], hand)
+                    // down cards are hidden from everyone but their owner,
+                    // at least until the game ends
+                    let visible = match self.phase {
+                        TestGamePhase::Ended => true,
+                        _ => player == Some(self.players[i].as_str()),
+                    };
+
+                    (
+                        &self.players[i],
+                        hand.iter()
+                            .map(|card| if visible { Some(*card) } else { None })
+                            .collect::<Vec<_>>()
+                    )
                 })
                 .collect::<HashMap<_, _>>(),
             "up_hands": self.up_hands.iter()
@@ -212,7 +528,7 @@ impl Game for TestGame {
     fn action(
         &mut self,
         action: GameAction,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), Error> {
         match (
             serde_json::from_value(action.0)?,
             self.phase
@@ -221,13 +537,13 @@ impl Game for TestGame {
             (TestGameAction::Draw{user, deck: _}, TestGamePhase::BeforeTurn) => {
                 let user = self.find_player(&user)?;
                 if user != self.current {
-                    Err(format!("not your turn!"))?;
+                    Err(Error::NotYourTurn)?;
                 }
 
                 // draw card
                 let card = self.deck.pop()
-                    .ok_or_else(|| format!(
-                        "attempted to draw from empty deck?"))?;
+                    .ok_or_else(|| Error::Internal(
+                        "attempted to draw from empty deck?".to_string()))?;
                 self.down_hands[user].push(card);
 
                 // move on to turn
@@ -238,7 +554,7 @@ impl Game for TestGame {
             (TestGameAction::Play{user, ..}, TestGamePhase::BeforeTurn) => {
                 let user = self.find_player(&user)?;
                 if user != self.current {
-                    Err(format!("not your turn!"))?;
+                    Err(Error::NotYourTurn)?;
                 }
 
                 self.log.push(format!(
@@ -252,16 +568,21 @@ impl Game for TestGame {
             (TestGameAction::Play{user, card, target}, TestGamePhase::Turn) => {
                 let user = self.find_player(&user)?;
                 if user != self.current {
-                    Err(format!("not your turn!"))?;
+                    Err(Error::NotYourTurn)?;
                 }
 
                 let target = self.find_player(&target)?;
 
+                // make sure we have the card before we touch anything
+                let i = self.down_hands[user].iter().position(|c| *c == card)
+                    .ok_or_else(|| Error::InvalidAction(format!("player {:?} doesn't have card {:?}?", user, card)))?;
+
                 // play the card
                 match card {
                     TestGameCard::Princess => {
                         // you can't play this one!
-                        Err(format!("tried to play princess"))?;
+                        Err(Error::InvalidAction(
+                            "tried to play princess".to_string()))?;
                     }
                     TestGameCard::Protect => {
                         // place card on target's "up" hand (yeah bad name)
@@ -272,10 +593,8 @@ impl Game for TestGame {
                         // how to discard...
                         if target != user {
                             // are they protected?
-                            if
-                                self.up_hands[target].iter()
-                                    .any(|c| *c == TestGameCard::Protect)
-                            {
+                            if self.up_hands[target]
+                                    .contains(&TestGameCard::Protect) {
                                 self.log.push(format!(
                                     "{} is protected...",
                                     self.players[target]
@@ -285,13 +604,12 @@ impl Game for TestGame {
 
                             // need to decide swap
                             let target_card = self.down_hands[target].pop()
-                                .ok_or_else(|| format!("target has no cards?"))?;
-                            self.down_hands[user].push(target_card);
+                                .ok_or_else(|| Error::Internal(
+                                    "target has no cards?".to_string()))?;
 
                             // remove card from hand
-                            let i = self.down_hands[user].iter().position(|c| *c == card)
-                                .ok_or_else(|| format!("player {:?} doesn't have card {:?}?", user, card))?;
                             self.down_hands[user].remove(i);
+                            self.down_hands[user].push(target_card);
 
                             // place on discard
                             self.discard.push(card);
@@ -304,7 +622,9 @@ impl Game for TestGame {
                                 self.players[target],
                             ));
 
-                            self.phase = TestGamePhase::DecidingStabby;
+                            self.phase = TestGamePhase::DecidingStabby {
+                                target,
+                            };
                             return Ok(());
                         }
 
@@ -314,8 +634,6 @@ impl Game for TestGame {
                 }
 
                 // remove card from hand
-                let i = self.down_hands[user].iter().position(|c| *c == card)
-                    .ok_or_else(|| format!("player {:?} doesn't have card {:?}?", user, card))?;
                 self.down_hands[user].remove(i);
 
                 // update log
@@ -331,24 +649,27 @@ impl Game for TestGame {
                 Ok(())
             }
             // decide swap
-            (TestGameAction::Play{user, card, target}, TestGamePhase::DecidingStabby) => {
+            (
+                TestGameAction::Play{user, card, target},
+                TestGamePhase::DecidingStabby{target: swapped},
+            ) => {
                 let user = self.find_player(&user)?;
                 if user != self.current {
-                    Err(format!("not your turn!"))?;
+                    Err(Error::NotYourTurn)?;
                 }
 
+                // can only give the card back to who we stabbed
                 let target = self.find_player(&target)?;
-
-                // hm, ok, so we didn't keep track of swap, but we know the
-                // one we're swapping with is the only one with no cards...
-                // this is sort of a hack, but should work
-                if self.down_hands[target].len() > 0 {
-                    Err(format!("not swapped player!"))?;
+                if target != swapped {
+                    Err(Error::InvalidAction(format!(
+                        "not swapped player! swapping with {:?}",
+                        self.players[swapped]
+                    )))?;
                 }
 
                 // remove card from hand
                 let i = self.down_hands[user].iter().position(|c| *c == card)
-                    .ok_or_else(|| format!("player {:?} doesn't have card {:?}?", user, card))?;
+                    .ok_or_else(|| Error::InvalidAction(format!("player {:?} doesn't have card {:?}?", user, card)))?;
                 self.down_hands[user].remove(i);
 
                 // give to target
@@ -358,12 +679,968 @@ impl Game for TestGame {
                 self.end_turn();
                 Ok(())
             }
+            // out of time, finish the turn for them
+            (TestGameAction::Timeout{user}, phase) => {
+                let user = self.find_player(&user)?;
+                if user != self.current {
+                    Err(Error::NotYourTurn)?;
+                }
+                if self.ended() {
+                    Err(Error::InvalidAction(
+                        "game already ended".to_string()))?;
+                }
+
+                self.log.push(format!(
+                    "{} ran out of time",
+                    self.players[user]
+                ));
+
+                match phase {
+                    TestGamePhase::BeforeTurn => {
+                        // draw, then discard
+                        let card = self.deck.pop()
+                            .ok_or_else(|| Error::Internal(
+                                "attempted to draw from empty deck?".to_string()))?;
+                        self.down_hands[user].push(card);
+                        self.discard(user)?;
+                    }
+                    TestGamePhase::Turn => {
+                        self.discard(user)?;
+                    }
+                    TestGamePhase::DecidingStabby{target} => {
+                        // give back whatever isn't the princess
+                        let i = self.down_hands[user].iter()
+                            .position(|c| *c != TestGameCard::Princess)
+                            .unwrap_or(0);
+                        let card = self.down_hands[user].remove(i);
+                        self.down_hands[target].push(card);
+                    }
+                    TestGamePhase::Ended => (),
+                }
+
+                // move on to next player
+                self.end_turn();
+                Ok(())
+            }
             _ => {
-                Err(format!(
+                Err(Error::InvalidAction(format!(
                     "invalid action during phase {:?}",
                     self.phase
-                ))?
+                )))?
             }
         }
     }
 }
+
+
+// -- bots --
+
+// what bots can see, parsed from the state we give players
+#[derive(Debug, Deserialize)]
+struct TestGameView {
+    players: Vec<String>,
+    current: String,
+    phase: String,
+    down_hands: HashMap<String, Vec<Option<TestGameCard>>>,
+    up_hands: HashMap<String, Vec<TestGameCard>>,
+    #[serde(default)]
+    stabby: Option<TestGameStabbyView>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TestGameStabbyView {
+    target: String,
+}
+
+impl TestGameView {
+    fn parse(state: &GameState) -> Option<Self> {
+        serde_json::from_value(state.0["game"].clone()).ok()
+    }
+
+    fn hand(&self, player: &str) -> Vec<TestGameCard> {
+        self.down_hands.get(player)
+            .map(|hand| hand.iter().flatten().copied().collect())
+            .unwrap_or_default()
+    }
+
+    fn protected(&self, player: &str) -> bool {
+        self.up_hands.get(player)
+            .is_some_and(|hand| hand.contains(&TestGameCard::Protect))
+    }
+
+    // other players we can actually stab
+    fn stabbable(&self, player: &str) -> Vec<&String> {
+        self.players.iter()
+            .filter(|p| *p != player && !self.protected(p))
+            .collect()
+    }
+}
+
+/// tries to hold on to the princess, and stab anyone who might have it
+#[derive(Debug)]
+pub struct TestGameHeuristicBot {
+    rng: StdRng,
+}
+
+impl TestGameHeuristicBot {
+    pub fn new() -> Self {
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Bot for TestGameHeuristicBot {
+    fn act(&mut self, player: &str, state: &GameState) -> Option<GameAction> {
+        let view = TestGameView::parse(state)?;
+        if view.current != player {
+            return None;
+        }
+
+        let hand = view.hand(player);
+        let has_princess = hand.contains(&TestGameCard::Princess);
+        match view.phase.as_str() {
+            "before_turn" => Some(draw_action(player)),
+            "turn" => {
+                let targets = view.stabbable(player);
+                if !has_princess
+                        && hand.contains(&TestGameCard::Stabby)
+                        && !targets.is_empty() {
+                    // someone else has the princess, go find it
+                    let target = targets.choose(&mut self.rng)?;
+                    Some(play_action(player, TestGameCard::Stabby, target))
+                } else if hand.contains(&TestGameCard::Protect) {
+                    // hold on to what we have
+                    Some(play_action(player, TestGameCard::Protect, player))
+                } else {
+                    // nothing useful to do, just discard
+                    Some(play_action(player, TestGameCard::Stabby, player))
+                }
+            }
+            "deciding_stabby" => {
+                // never give back the princess
+                let card = hand.iter()
+                    .find(|c| **c != TestGameCard::Princess)
+                    .or_else(|| hand.first())?;
+                Some(play_action(player, *card, &view.stabby?.target))
+            }
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use rand::Rng;
+
+    fn new_game(n: usize, seed: u64) -> TestGame {
+        TestGame::new(
+            (0..n).map(|i| format!("p{}", i)).collect(),
+            seed,
+            TestGameOptions::default()
+        ).unwrap()
+    }
+
+    fn act(game: &mut TestGame, action: serde_json::Value) -> Result<(), Error> {
+        game.action(GameAction(action))
+    }
+
+    fn draw(game: &mut TestGame, user: usize) -> Result<(), Error> {
+        let user = game.players[user].clone();
+        act(game, serde_json::json!({
+            "action": "draw",
+            "user": user,
+            "deck": "deck",
+        }))
+    }
+
+    fn play(
+        game: &mut TestGame,
+        user: usize,
+        card: TestGameCard,
+        target: usize,
+    ) -> Result<(), Error> {
+        let user = game.players[user].clone();
+        let target = game.players[target].clone();
+        act(game, serde_json::json!({
+            "action": "play",
+            "user": user,
+            "card": card,
+            "target": target,
+        }))
+    }
+
+    // set up hands explicitly, everything else goes in the deck
+    fn rig(game: &mut TestGame, hands: Vec<Vec<TestGameCard>>) {
+        let mut cards = game.deck.drain(..)
+            .chain(game.down_hands.drain(..).flatten())
+            .collect::<Vec<_>>();
+        for hand in hands.iter() {
+            for card in hand.iter() {
+                let i = cards.iter().position(|c| c == card).unwrap();
+                cards.remove(i);
+            }
+        }
+        game.down_hands = hands;
+        game.deck = cards;
+    }
+
+    fn count(game: &TestGame, card: TestGameCard) -> usize {
+        game.deck.iter()
+            .chain(game.down_hands.iter().flatten())
+            .chain(game.up_hands.iter().flatten())
+            .chain(game.discard.iter())
+            .filter(|c| **c == card)
+            .count()
+    }
+
+    fn check_conservation(game: &TestGame) {
+        check_conservation_for(game, game.players.len());
+    }
+
+    // players may have left, but their cards haven't
+    fn check_conservation_for(game: &TestGame, n: usize) {
+        assert_eq!(count(game, TestGameCard::Princess), 1);
+        assert_eq!(count(game, TestGameCard::Protect), n.div_ceil(2));
+        assert_eq!(count(game, TestGameCard::Stabby), (n+1)*2);
+    }
+
+    fn winner(game: &TestGame) -> Option<usize> {
+        game.log.iter()
+            .filter_map(|msg| msg.strip_suffix(" wins!"))
+            .map(|name| game.players.iter().position(|p| p == name).unwrap())
+            .next()
+    }
+
+    // pick a random legal action for whoever needs to act
+    fn random_legal(game: &mut TestGame, rng: &mut impl Rng) {
+        // sometimes players wander off
+        if rng.gen_bool(0.1) {
+            let action = game.timeout_action().unwrap();
+            game.action(action).unwrap();
+            return;
+        }
+
+        let player = game.players[game.current].clone();
+        let actions = game.legal_actions(&player).unwrap();
+        let action = actions[rng.gen_range(0..actions.len())].clone();
+        game.action(action).unwrap();
+    }
+
+    // the room's view of things, which is what bots see
+    fn room_state_for(game: &TestGame, player: &str) -> GameState {
+        GameState(serde_json::json!({
+            "game": game.state_for(Some(player)),
+            "legal_actions": game.legal_actions(player),
+        }))
+    }
+
+    #[test]
+    fn legal_actions() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Princess, TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.up_hands[2].push(TestGameCard::Protect);
+        game.deck.retain(|c| *c != TestGameCard::Protect);
+        game.phase = TestGamePhase::Turn;
+
+        // no princess, and no stabbing p2
+        let actions = game.legal_actions("p0").unwrap();
+        let targets = actions.iter()
+            .map(|action| {
+                assert_eq!(action.0["card"], "stabby");
+                action.0["target"].as_str().unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec!["p0", "p1"]);
+
+        // nothing to do if it's not our turn
+        assert_eq!(game.legal_actions("p1").unwrap().len(), 0);
+        assert_eq!(game.legal_actions("nobody").unwrap().len(), 0);
+
+        // only one thing to do when swapping
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+        let actions = game.legal_actions("p0").unwrap();
+        assert_eq!(actions.len(), 2);
+        assert!(actions.iter().all(|action| action.0["target"] == "p1"));
+    }
+
+    #[test]
+    fn deals_one_card_each() {
+        for n in 1..8 {
+            let game = new_game(n, 0);
+            assert!(game.down_hands.iter().all(|hand| hand.len() == 1));
+            assert!(game.up_hands.iter().all(|hand| hand.is_empty()));
+            assert_eq!(game.deck.len(), 1 + n.div_ceil(2) + (n+1)*2 - n);
+            check_conservation(&game);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let a = new_game(4, 1234);
+        let b = new_game(4, 1234);
+        assert_eq!(a.players, b.players);
+        assert_eq!(a.deck, b.deck);
+        assert_eq!(a.down_hands, b.down_hands);
+    }
+
+    #[test]
+    fn draw_then_play() {
+        let mut game = new_game(3, 0);
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        draw(&mut game, 0).unwrap();
+        assert!(matches!(game.phase, TestGamePhase::Turn));
+        assert_eq!(game.down_hands[0].len(), 2);
+
+        // can't draw twice
+        assert!(matches!(
+            draw(&mut game, 0),
+            Err(Error::InvalidAction(_))
+        ));
+    }
+
+    #[test]
+    fn not_your_turn() {
+        let mut game = new_game(3, 0);
+        assert_eq!(draw(&mut game, 1), Err(Error::NotYourTurn));
+        draw(&mut game, 0).unwrap();
+        assert_eq!(
+            play(&mut game, 1, TestGameCard::Stabby, 1),
+            Err(Error::NotYourTurn)
+        );
+    }
+
+    #[test]
+    fn not_playing() {
+        let mut game = new_game(2, 0);
+        assert_eq!(
+            act(&mut game, serde_json::json!({
+                "action": "draw",
+                "user": "nobody",
+                "deck": "deck",
+            })),
+            Err(Error::NotPlaying("nobody".to_string()))
+        );
+    }
+
+    #[test]
+    fn bad_message() {
+        let mut game = new_game(2, 0);
+        assert!(matches!(
+            act(&mut game, serde_json::json!({"action": "dance"})),
+            Err(Error::BadMessage(_))
+        ));
+    }
+
+    #[test]
+    fn play_before_draw() {
+        let mut game = new_game(2, 0);
+        let card = game.down_hands[0][0];
+        play(&mut game, 0, card, 0).unwrap();
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        assert_eq!(game.down_hands[0].len(), 1);
+        assert!(game.log.last().unwrap().ends_with("needs to draw.."));
+    }
+
+    #[test]
+    fn cant_play_princess() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Princess, TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+        assert!(matches!(
+            play(&mut game, 0, TestGameCard::Princess, 1),
+            Err(Error::InvalidAction(_))
+        ));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn cant_play_missing_card() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+        assert!(matches!(
+            play(&mut game, 0, TestGameCard::Protect, 1),
+            Err(Error::InvalidAction(_))
+        ));
+        assert_eq!(game.up_hands[1].len(), 0);
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn turn_order() {
+        let mut game = new_game(3, 0);
+        for turn in 0..6 {
+            let user = game.current;
+            assert_eq!(user, turn % 3);
+            draw(&mut game, user).unwrap();
+            play(&mut game, user, TestGameCard::Stabby, user)
+                .or_else(|_| play(&mut game, user, TestGameCard::Protect, user))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn protect_blocks_stabby() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Stabby],
+            vec![TestGameCard::Princess],
+        ]);
+        game.up_hands[1].push(TestGameCard::Protect);
+        game.discard.clear();
+        let deck_protect = game.deck.iter()
+            .position(|c| *c == TestGameCard::Protect);
+        if let Some(i) = deck_protect {
+            game.deck.remove(i);
+        }
+        game.phase = TestGamePhase::Turn;
+
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+        assert_eq!(game.down_hands[1], vec![TestGameCard::Princess]);
+        assert_eq!(game.down_hands[0].len(), 2);
+        assert!(matches!(game.phase, TestGamePhase::Turn));
+        assert!(game.log.last().unwrap().ends_with("is protected..."));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn protect_lasts_until_next_turn() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.deck.retain(|c| *c != TestGameCard::Protect);
+        game.deck.push(TestGameCard::Protect);
+
+        // p0 protects themself
+        draw(&mut game, 0).unwrap();
+        play(&mut game, 0, TestGameCard::Protect, 0).unwrap();
+        assert_eq!(game.up_hands[0], vec![TestGameCard::Protect]);
+
+        // p1 can't stab p0
+        draw(&mut game, 1).unwrap();
+        play(&mut game, 1, TestGameCard::Stabby, 0).unwrap();
+        assert!(game.log.last().unwrap().ends_with("is protected..."));
+        play(&mut game, 1, TestGameCard::Stabby, 1).unwrap();
+
+        // and then p0's protection is cleared
+        assert_eq!(game.current, 0);
+        assert_eq!(game.up_hands[0].len(), 0);
+        assert_eq!(game.discard.last(), Some(&TestGameCard::Protect));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn stabby_swap() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Protect],
+            vec![TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+
+        // p0 takes p1's card
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+        assert!(matches!(game.phase, TestGamePhase::DecidingStabby{target: 1}));
+        assert_eq!(game.down_hands[0].len(), 2);
+        assert!(game.down_hands[0].contains(&TestGameCard::Princess));
+        assert_eq!(game.down_hands[1].len(), 0);
+
+        // only p1 can get a card back
+        assert!(play(&mut game, 0, TestGameCard::Protect, 2).is_err());
+        play(&mut game, 0, TestGameCard::Protect, 1).unwrap();
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Princess]);
+        assert_eq!(game.down_hands[1], vec![TestGameCard::Protect]);
+        assert_eq!(game.current, 1);
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn stabby_swap_remembers_target() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Protect],
+            vec![TestGameCard::Stabby],
+            vec![],
+        ]);
+        game.phase = TestGamePhase::Turn;
+
+        // p2 also has no cards, but isn't the one we stabbed
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+        assert!(matches!(
+            play(&mut game, 0, TestGameCard::Protect, 2),
+            Err(Error::InvalidAction(_))
+        ));
+
+        // everyone can see who is deciding
+        let state = game.state_for(Some("p2")).0;
+        assert_eq!(state["phase"], "deciding_stabby");
+        assert_eq!(state["stabby"]["user"], "p0");
+        assert_eq!(state["stabby"]["target"], "p1");
+
+        play(&mut game, 0, TestGameCard::Protect, 1).unwrap();
+        assert_eq!(game.down_hands[1], vec![TestGameCard::Protect]);
+        assert_eq!(game.down_hands[2].len(), 0);
+        assert!(game.state_for(None).0["stabby"].is_null());
+    }
+
+    #[test]
+    fn timeout_draws_and_discards() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+            vec![TestGameCard::Stabby],
+        ]);
+        // protect on top
+        let i = game.deck.iter()
+            .position(|c| *c == TestGameCard::Protect)
+            .unwrap();
+        let card = game.deck.remove(i);
+        game.deck.push(card);
+
+        assert_eq!(game.current_player(), Some("p0".to_string()));
+        let action = game.timeout_action().unwrap();
+        assert_eq!(action.0["user"], "p0");
+        game.action(action).unwrap();
+
+        // drew the protect, kept the princess
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Princess]);
+        assert_eq!(game.discard, vec![TestGameCard::Protect]);
+        assert_eq!(game.current, 1);
+        assert!(game.log.iter().any(|msg| msg == "p0 ran out of time"));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn timeout_during_turn() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+
+        let action = game.timeout_action().unwrap();
+        game.action(action).unwrap();
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Princess]);
+        assert_eq!(game.current, 1);
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn timeout_while_deciding_stabby() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Stabby],
+            vec![TestGameCard::Princess],
+        ]);
+        game.phase = TestGamePhase::Turn;
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+
+        // the stabbed player gets their card back, not the princess
+        let action = game.timeout_action().unwrap();
+        game.action(action).unwrap();
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Princess]);
+        assert_eq!(game.down_hands[1], vec![TestGameCard::Stabby]);
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        check_conservation(&game);
+    }
+
+    #[test]
+    fn timeout_only_for_current_player() {
+        let mut game = new_game(2, 0);
+        assert_eq!(
+            act(&mut game, serde_json::json!({
+                "action": "timeout",
+                "user": "p1",
+            })),
+            Err(Error::NotYourTurn)
+        );
+    }
+
+    #[test]
+    fn leaving_passes_the_turn() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+            vec![TestGameCard::Protect],
+        ]);
+        game.phase = TestGamePhase::Turn;
+
+        game.player_left("p0").unwrap();
+        assert_eq!(game.players, vec![format!("p1"), format!("p2")]);
+        assert_eq!(game.current_player(), Some("p1".to_string()));
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        assert!(game.log.iter().any(|msg| msg == "p0 left the game"));
+
+        // the princess can still be found
+        assert_eq!(game.deck[..2], [TestGameCard::Stabby, TestGameCard::Princess]);
+        check_conservation_for(&game, 3);
+
+        // and they're gone for good
+        assert!(matches!(
+            game.player_left("p0"),
+            Err(Error::NotPlaying(_))
+        ));
+    }
+
+    #[test]
+    fn leaving_out_of_turn() {
+        let mut game = new_game(4, 0);
+        game.current = 2;
+        game.phase = TestGamePhase::Turn;
+
+        // after the current player
+        game.player_left("p3").unwrap();
+        assert_eq!(game.current_player(), Some("p2".to_string()));
+        assert!(matches!(game.phase, TestGamePhase::Turn));
+
+        // before the current player
+        game.player_left("p0").unwrap();
+        assert_eq!(game.current, 1);
+        assert_eq!(game.current_player(), Some("p2".to_string()));
+        assert!(matches!(game.phase, TestGamePhase::Turn));
+        check_conservation_for(&game, 4);
+    }
+
+    #[test]
+    fn leaving_while_stabbed() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Protect],
+            vec![TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+
+        // no one to give a card back to, so it's discarded
+        game.player_left("p1").unwrap();
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Princess]);
+        assert_eq!(game.discard.last(), Some(&TestGameCard::Protect));
+        assert_eq!(game.current_player(), Some("p2".to_string()));
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        check_conservation_for(&game, 3);
+    }
+
+    #[test]
+    fn leaving_while_stabbing() {
+        let mut game = new_game(3, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby, TestGameCard::Protect],
+            vec![TestGameCard::Princess],
+            vec![TestGameCard::Stabby],
+        ]);
+        game.phase = TestGamePhase::Turn;
+        play(&mut game, 0, TestGameCard::Stabby, 1).unwrap();
+
+        // the target gets something back, but not the princess
+        game.player_left("p0").unwrap();
+        assert_eq!(game.down_hands[0], vec![TestGameCard::Protect]);
+        assert_eq!(game.deck[0], TestGameCard::Princess);
+        assert_eq!(game.current_player(), Some("p1".to_string()));
+        assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+        check_conservation_for(&game, 3);
+    }
+
+    #[test]
+    fn last_player_standing_wins() {
+        let mut game = new_game(2, 0);
+        game.player_left("p0").unwrap();
+        assert!(game.ended());
+        assert_eq!(winner(&game), Some(0));
+        assert_eq!(game.current_player(), None);
+        assert_eq!(game.state_for(None).0["current"], "p1");
+        check_conservation_for(&game, 2);
+
+        // leaving after the game is over changes nothing
+        let log = game.log.clone();
+        game.player_left("p1").unwrap();
+        assert_eq!(game.log, log);
+        assert_eq!(game.players, vec![format!("p1")]);
+    }
+
+    #[test]
+    fn princess_wins() {
+        let mut game = new_game(2, 0);
+        rig(&mut game, vec![
+            vec![TestGameCard::Stabby],
+            vec![TestGameCard::Princess],
+        ]);
+        game.deck.truncate(1);
+        game.discard.clear();
+
+        // deck runs out after this turn
+        draw(&mut game, 0).unwrap();
+        let card = game.down_hands[0][1];
+        play(&mut game, 0, card, 0).unwrap();
+        assert!(game.ended());
+        assert_eq!(game.current_player(), None);
+        assert!(game.timeout_action().is_none());
+        assert_eq!(winner(&game), Some(1));
+        assert_eq!(game.status(), "ended");
+
+        // and nothing else can happen
+        assert!(draw(&mut game, 1).is_err());
+    }
+
+    #[test]
+    fn options_change_the_deck() {
+        let players = vec![format!("p0"), format!("p1")];
+        let game = TestGame::new(players.clone(), 0, TestGameOptions {
+            protects: Some(0),
+            stabbies: Some(10),
+        }).unwrap();
+        assert_eq!(count(&game, TestGameCard::Princess), 1);
+        assert_eq!(count(&game, TestGameCard::Protect), 0);
+        assert_eq!(count(&game, TestGameCard::Stabby), 10);
+
+        // everyone needs a card, with one left to draw
+        assert!(matches!(
+            TestGame::new(players.clone(), 0, TestGameOptions {
+                protects: Some(0),
+                stabbies: Some(1),
+            }),
+            Err(Error::InvalidAction(_))
+        ));
+        assert!(TestGame::new(players, 0, TestGameOptions {
+            protects: Some(1),
+            stabbies: Some(1),
+        }).is_ok());
+    }
+
+    #[test]
+    fn hidden_hands() {
+        let game = new_game(3, 0);
+        let state = game.state_for(Some("p1")).0;
+        let hands = &state["down_hands"];
+        assert!(hands["p1"][0].is_string());
+        assert!(hands["p0"][0].is_null());
+        assert!(hands["p2"][0].is_null());
+
+        let state = game.state_for(None).0;
+        assert!(state["down_hands"]["p1"][0].is_null());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut game = new_game(3, 42);
+        draw(&mut game, 0).unwrap();
+        let loaded = TestGame::load(game.save().unwrap()).unwrap();
+        assert_eq!(
+            loaded.state_for(Some("p0")).0,
+            game.state_for(Some("p0")).0
+        );
+    }
+
+    // bots should only ever make legal moves
+    fn play_bots(bots: &mut [Box<dyn Bot>], seed: u64) -> TestGame {
+        let mut game = new_game(bots.len(), seed);
+        let mut moves = 0;
+        while !game.ended() {
+            for (i, bot) in bots.iter_mut().enumerate() {
+                let name = format!("p{}", i);
+                if let Some(action) = bot.act(&name, &room_state_for(&game, &name)) {
+                    game.action(action).unwrap();
+                    check_conservation(&game);
+                    moves += 1;
+                }
+            }
+
+            assert!(moves < 1000, "bots stuck?");
+        }
+        game
+    }
+
+    #[test]
+    fn random_bots_finish() {
+        for seed in 0..20 {
+            let mut bots = (0..4)
+                .map(|_| Box::new(RandomBot::new()) as Box<dyn Bot>)
+                .collect::<Vec<_>>();
+            let game = play_bots(&mut bots, seed);
+            assert!(winner(&game).is_some());
+        }
+    }
+
+    #[test]
+    fn heuristic_bots_finish() {
+        for seed in 0..20 {
+            let mut bots = (0..4)
+                .map(|i| {
+                    if i % 2 == 0 {
+                        Box::new(TestGameHeuristicBot::new()) as Box<dyn Bot>
+                    } else {
+                        Box::new(RandomBot::new()) as Box<dyn Bot>
+                    }
+                })
+                .collect::<Vec<_>>();
+            let game = play_bots(&mut bots, seed);
+            assert!(winner(&game).is_some());
+        }
+    }
+
+    #[test]
+    fn bots_wait_their_turn() {
+        let game = new_game(2, 0);
+        let waiting = game.players[1].clone();
+        let mut bot = TestGameHeuristicBot::new();
+        assert!(bot.act(&waiting, &room_state_for(&game, &waiting)).is_none());
+    }
+
+    proptest! {
+        #[test]
+        fn random_games_end_with_princess_winner(
+            seed in any::<u64>(),
+            n in 2usize..8,
+        ) {
+            let mut game = new_game(n, seed);
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let mut turns = 0;
+            while !game.ended() {
+                // everything we say is legal actually is
+                let player = game.players[game.current].clone();
+                for action in game.legal_actions(&player).unwrap() {
+                    let mut copy = TestGame::load(game.save().unwrap()).unwrap();
+                    prop_assert!(copy.action(action).is_ok());
+                }
+
+                let before = (game.current, game.phase);
+                random_legal(&mut game, &mut rng);
+                check_conservation(&game);
+
+                // turns always go to the next player
+                if game.current != before.0 {
+                    prop_assert_eq!(game.current, (before.0+1) % n);
+                    prop_assert!(matches!(game.phase, TestGamePhase::BeforeTurn));
+                    turns += 1;
+                }
+
+                // nobody holds more than two cards, and hidden cards
+                // stay hidden from everyone but their owner
+                if !game.ended() {
+                    prop_assert!(game.down_hands.iter().all(|hand| hand.len() <= 2));
+
+                    let player = &game.players[game.current];
+                    let state = game.state_for(Some(player)).0;
+                    for (name, hand) in state["down_hands"].as_object().unwrap() {
+                        let hand = hand.as_array().unwrap();
+                        if name == player {
+                            prop_assert!(hand.iter().all(|card| !card.is_null()));
+                        } else {
+                            prop_assert!(hand.iter().all(|card| card.is_null()));
+                        }
+                    }
+                }
+            }
+
+            // one turn per card in the deck
+            prop_assert_eq!(turns + 1, 1 + n.div_ceil(2) + (n+1)*2 - n);
+
+            // whoever has the princess wins
+            let princess = game.down_hands.iter()
+                .position(|hand| hand.contains(&TestGameCard::Princess));
+            prop_assert!(princess.is_some());
+            prop_assert_eq!(winner(&game), princess);
+        }
+
+        #[test]
+        fn random_actions_conserve_cards(
+            seed in any::<u64>(),
+            n in 1usize..6,
+            actions in prop::collection::vec(
+                (any::<bool>(), 0usize..8, 0usize..3, 0usize..8),
+                0..200
+            ),
+        ) {
+            let mut game = new_game(n, seed);
+            let cards = [
+                TestGameCard::Princess,
+                TestGameCard::Protect,
+                TestGameCard::Stabby,
+            ];
+            for (is_draw, user, card, target) in actions {
+                // users and targets may not even exist
+                let name = |i: usize| format!("p{}", i);
+                let action = if is_draw {
+                    serde_json::json!({
+                        "action": "draw",
+                        "user": name(user),
+                        "deck": "deck",
+                    })
+                } else {
+                    serde_json::json!({
+                        "action": "play",
+                        "user": name(user),
+                        "card": cards[card],
+                        "target": name(target),
+                    })
+                };
+
+                let was_ended = game.ended();
+                let res = act(&mut game, action);
+                check_conservation(&game);
+                prop_assert!(game.current < n);
+                if was_ended {
+                    prop_assert!(res.is_err());
+                }
+            }
+        }
+
+        #[test]
+        fn random_departures_end_with_a_winner(
+            seed in any::<u64>(),
+            n in 2usize..8,
+        ) {
+            let mut game = new_game(n, seed);
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            while !game.ended() {
+                if rng.gen_bool(0.05) {
+                    let i = rng.gen_range(0..game.players.len());
+                    let player = game.players[i].clone();
+                    game.player_left(&player).unwrap();
+                    prop_assert!(!game.players.contains(&player));
+                } else {
+                    random_legal(&mut game, &mut rng);
+                }
+                check_conservation_for(&game, n);
+
+                // whoever's up can always do something
+                if !game.ended() {
+                    prop_assert!(game.players.len() >= 2);
+                    let player = game.current_player().unwrap();
+                    prop_assert!(!game.legal_actions(&player).unwrap().is_empty());
+                }
+            }
+
+            // someone always wins, either with the princess or by
+            // outlasting everyone else
+            let winner = winner(&game);
+            prop_assert!(winner.is_some());
+            prop_assert!(
+                game.players.len() == 1
+                    || game.down_hands[winner.unwrap()]
+                        .contains(&TestGameCard::Princess)
+            );
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! games loaded from WebAssembly modules
+//!
+//! Game rules can be shipped as .wasm files, which are loaded from a
+//! directory at startup and registered like any other game. JSON crosses
+//! the boundary in both directions, and modules don't keep anything
+//! between calls, we hold on to the game's state and pass it in with
+//! every call.
+//!
+//! Every export takes (ptr: i32, len: i32), a JSON input the host
+//! wrote into memory it got from alloc, and returns an i64 with a
+//! pointer to the JSON output in the high 32 bits and its length in the
+//! low 32 bits. A module exports:
+//!
+//! - memory
+//! - alloc(len: i32) -> i32, somewhere for us to write the input
+//! - info: {} -> {id, name, description, min_players, max_players,
+//!   assets, options}, assets are relative to the games directory and
+//!   options is a JSON schema, see options.rs
+//! - create: {players, seed, options} -> state
+//! - state: {state, player} -> the state as seen by player, or the
+//!   public view if player is null
+//! - action: {state, action} -> {state} or {error}
+//! - status: {state} -> string
+//! - ended: {state} -> bool
+//!
+//! And optionally, mirroring the rest of the Game trait:
+//!
+//! - current_player: {state} -> string or null
+//! - legal_actions: {state, player} -> array of actions or null
+//! - timeout_action: {state} -> action or null, the action must be
+//!   called "timeout", clients can't send those
+//! - player_left: {state, player} -> {state} or {error}
+//!
+//! Modules are sandboxed. They get no imports, so they can't touch
+//! anything but their own memory, and each call runs in a fresh instance
+//! with limited fuel and memory. A call that traps or runs out fails
+//! with an error, and the game's state is left as it was.
+//!
+//! Calls run right on the room's thread, so fuel is what keeps a module
+//! from holding up every other room on it. Exports that only read the
+//! state get much less fuel than ones that change it, and their results
+//! are kept until the state changes, since rooms ask for the same views
+//! over and over.
+
+use crate::bot::*;
+use crate::error::*;
+use crate::game::*;
+use crate::options;
+use crate::registry::*;
+use crate::ErrorEx;
+use log::*;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// instructions, roughly, calls that change the state get
+const UPDATE_FUEL: u64 = 100_000_000;
+
+/// instructions, roughly, calls that only read the state get
+const READ_FUEL: u64 = 5_000_000;
+
+/// most memory each call gets, in bytes
+const MEMORY_LIMIT: usize = 64*1024*1024;
+
+/// largest output we'll read back, in bytes
+const OUTPUT_LIMIT: usize = 16*1024*1024;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all="snake_case")]
+struct WasmGameInfo {
+    id: String,
+    name: String,
+    #[serde(default)]
+    description: String,
+    min_players: usize,
+    max_players: usize,
+    #[serde(default)]
+    assets: Vec<String>,
+    #[serde(default="options::no_options")]
+    options: Value,
+}
+
+/// what each call's store holds on to
+struct WasmLimits(wasmi::StoreLimits);
+
+/// a compiled module, instantiated fresh for every call
+#[derive(Debug)]
+struct WasmModule {
+    name: String,
+    engine: wasmi::Engine,
+    module: wasmi::Module,
+}
+
+impl WasmModule {
+    fn new(name: &str, wasm: &[u8]) -> Result<Self, Error> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&config);
+        let module = wasmi::Module::new(&engine, wasm)
+            .map_err(|err| Error::Internal(format!(
+                "can't load game {:?}: {}", name, err)))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            engine,
+            module,
+        })
+    }
+
+    fn exports(&self, export: &str) -> bool {
+        self.module.get_export(export).is_some()
+    }
+
+    /// call an export, anything that goes wrong in the module is an
+    /// internal error
+    fn call(
+        &self,
+        export: &str,
+        input: &Value,
+        fuel: u64,
+    ) -> Result<Value, Error> {
+        let err = |err: &dyn std::fmt::Display| {
+            Error::Internal(format!(
+                "game {:?} failed in {}: {}", self.name, export, err))
+        };
+
+        let mut store = wasmi::Store::new(
+            &self.engine,
+            WasmLimits(wasmi::StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build())
+        );
+        store.limiter(|limits| &mut limits.0);
+        store.set_fuel(fuel).map_err(|e| err(&e))?;
+
+        // no imports, the module only gets its own memory
+        let linker = wasmi::Linker::<WasmLimits>::new(&self.engine);
+        let instance = linker.instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| err(&e))?;
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or_else(|| err(&"no memory exported"))?;
+
+        // write the input
+        let input = serde_json::to_vec(input)?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| err(&e))?;
+        let ptr = alloc.call(&mut store, input.len() as i32)
+            .map_err(|e| err(&e))?;
+        memory.write(&mut store, ptr as u32 as usize, &input)
+            .map_err(|e| err(&e))?;
+
+        // call the thing
+        let func = instance.get_typed_func::<(i32, i32), i64>(&store, export)
+            .map_err(|e| err(&e))?;
+        let output = func.call(&mut store, (ptr, input.len() as i32))
+            .map_err(|e| err(&e))? as u64;
+
+        // and read the output
+        let ptr = (output >> 32) as usize;
+        let len = (output & 0xffffffff) as usize;
+        if len > OUTPUT_LIMIT {
+            Err(err(&format!("output too large, {} bytes", len)))?;
+        }
+        let mut output = vec![0; len];
+        memory.read(&store, ptr, &mut output)
+            .map_err(|e| err(&e))?;
+        serde_json::from_slice(&output).map_err(|e| err(&e))
+    }
+
+    /// call an export that either gives us a new state or an error
+    /// for the player
+    fn call_update(&self, export: &str, input: &Value) -> Result<Value, Error> {
+        let mut output = self.call(export, input, UPDATE_FUEL)?;
+        if let Some(error) = output.get("error") {
+            Err(Error::InvalidAction(match error {
+                Value::String(error) => error.clone(),
+                error => error.to_string(),
+            }))?;
+        }
+
+        match output.get_mut("state") {
+            Some(state) => Ok(state.take()),
+            None => Err(Error::Internal(format!(
+                "game {:?} gave neither state nor error from {}",
+                self.name, export))),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WasmGameFactory {
+    info: WasmGameInfo,
+    module: Arc<WasmModule>,
+}
+
+impl WasmGameFactory {
+    pub fn new(name: &str, wasm: &[u8]) -> Result<Self, Error> {
+        let module = WasmModule::new(name, wasm)?;
+        for export in ["info", "create", "state", "action", "status", "ended"] {
+            if !module.exports(export) {
+                Err(Error::Internal(format!(
+                    "game {:?} doesn't export {}", name, export)))?;
+            }
+        }
+
+        let info = serde_json::from_value(
+            module.call("info", &serde_json::json!({}), UPDATE_FUEL)?
+        )?;
+        Ok(Self {
+            info,
+            module: Arc::new(module),
+        })
+    }
+}
+
+impl GameFactory for WasmGameFactory {
+    fn id(&self) -> &str {
+        &self.info.id
+    }
+
+    fn name(&self) -> &str {
+        &self.info.name
+    }
+
+    fn description(&self) -> &str {
+        &self.info.description
+    }
+
+    fn min_players(&self) -> usize {
+        self.info.min_players
+    }
+
+    fn max_players(&self) -> usize {
+        self.info.max_players
+    }
+
+    fn assets(&self) -> Vec<String> {
+        self.info.assets.iter()
+            .map(|asset| format!("games/{}", asset))
+            .collect()
+    }
+
+    fn options_schema(&self) -> Value {
+        self.info.options.clone()
+    }
+
+    fn create(
+        &self,
+        players: Vec<String>,
+        seed: u64,
+        options: Value,
+    ) -> Result<Box<dyn Game>, Error> {
+        let state = self.module.call("create", &serde_json::json!({
+            "players": players,
+            "seed": seed,
+            "options": options,
+        }), UPDATE_FUEL)?;
+        Ok(Box::new(WasmGame::new(self.module.clone(), state)))
+    }
+
+    fn load(&self, state: Value) -> Result<Box<dyn Game>, Error> {
+        Ok(Box::new(WasmGame::new(self.module.clone(), state)))
+    }
+
+    fn bot(&self, kind: BotKind) -> Result<Box<dyn Bot>, Error> {
+        // random bots only need to know what's legal
+        match kind {
+            BotKind::Random if self.module.exports("legal_actions") => {
+                Ok(Box::new(RandomBot::new()))
+            }
+            _ => Err(Error::NoSuchBot(format!("{:?}", kind).to_lowercase())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WasmGame {
+    module: Arc<WasmModule>,
+    state: Value,
+    // what read-only exports gave us for the current state, by export
+    // and arguments, failures included so we don't keep retrying them
+    reads: RefCell<HashMap<String, Result<Value, Error>>>,
+}
+
+impl WasmGame {
+    fn new(module: Arc<WasmModule>, state: Value) -> Self {
+        Self {
+            module,
+            state,
+            reads: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// call an export that only reads the state, args are any extra
+    /// fields for the input
+    fn read(&self, export: &str, args: Value) -> Result<Value, Error> {
+        let key = format!("{} {}", export, args);
+        if let Some(output) = self.reads.borrow().get(&key) {
+            return output.clone();
+        }
+
+        let mut input = args;
+        input["state"] = self.state.clone();
+        let output = self.module.call(export, &input, READ_FUEL).warn_err();
+        self.reads.borrow_mut().insert(key, output.clone());
+        output
+    }
+
+    /// read an optional export, None if it's not there or fails
+    fn read_optional(&self, export: &str, args: Value) -> Option<Value> {
+        if !self.module.exports(export) {
+            return None;
+        }
+
+        self.read(export, args).ok()
+            .filter(|output| !output.is_null())
+    }
+
+    /// call an export that changes the state
+    fn update(&mut self, export: &str, mut input: Value) -> Result<(), Error> {
+        input["state"] = self.state.clone();
+        self.state = self.module.call_update(export, &input)?;
+        self.reads.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+impl Game for WasmGame {
+    fn status(&self) -> String {
+        self.read("status", serde_json::json!({}))
+            .ok()
+            .and_then(|status| status.as_str().map(|s| s.to_string()))
+            .unwrap_or_else(|| "game crashed?".to_string())
+    }
+
+    fn state_for(&self, player: Option<&str>) -> GameState {
+        GameState(
+            self.read("state", serde_json::json!({"player": player}))
+                .unwrap_or_else(|err| serde_json::json!({"error": err}))
+        )
+    }
+
+    fn save(&self) -> Result<Value, Error> {
+        Ok(self.state.clone())
+    }
+
+    fn ended(&self) -> bool {
+        self.read("ended", serde_json::json!({}))
+            .ok()
+            .and_then(|ended| ended.as_bool())
+            .unwrap_or(false)
+    }
+
+    fn current_player(&self) -> Option<String> {
+        self.read_optional("current_player", serde_json::json!({}))
+            .and_then(|player| player.as_str().map(|s| s.to_string()))
+    }
+
+    fn legal_actions(&self, player: &str) -> Option<Vec<GameAction>> {
+        self.read_optional("legal_actions", serde_json::json!({"player": player}))
+            .and_then(|actions| serde_json::from_value(actions).ok())
+    }
+
+    fn timeout_action(&self) -> Option<GameAction> {
+        self.read_optional("timeout_action", serde_json::json!({}))
+            .map(GameAction)
+    }
+
+    fn player_left(&mut self, player: &str) -> Result<(), Error> {
+        if !self.module.exports("player_left") {
+            Err(Error::InvalidAction(
+                "can't leave this game until it's over".to_string()))?;
+        }
+
+        self.update("player_left", serde_json::json!({"player": player}))
+    }
+
+    fn action(&mut self, action: GameAction) -> Result<(), Error> {
+        self.update("action", serde_json::json!({"action": action.0}))
+    }
+}
+
+/// load every .wasm file in a directory, registering each as a game,
+/// games that fail to load are skipped
+pub fn load_games(dir: &Path) -> Result<(), Error> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    for path in paths {
+        if path.extension().map(|ext| ext != "wasm").unwrap_or(true) {
+            continue;
+        }
+
+        let name = path.file_stem()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let res = fs::read(&path)
+            .map_err(Error::from)
+            .and_then(|wasm| WasmGameFactory::new(&name, &wasm))
+            .and_then(|factory| {
+                info!("loaded game {:?} from {:?}", factory.id(), path);
+                register(Arc::new(factory))
+            });
+        if let Err(err) = res {
+            warn!("can't load game from {:?}: {}", path, err);
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a tiny game, its state is whatever it was created with, and it
+    // breaks in all sorts of ways
+    fn echo_game() -> String {
+        let consts = [
+            ("info", r#"{"id":"echo","name":"Echo","min_players":1,"max_players":2}"#),
+            ("status", r#""playing""#),
+            ("ended", "false"),
+            ("player_left", r#"{"error":"stay put"}"#),
+        ];
+
+        let mut data = String::new();
+        let mut funcs = String::new();
+        let mut offset = 0;
+        for (name, json) in consts.iter() {
+            data.push_str(&format!(
+                "(data (i32.const {}) {:?})\n", offset, json));
+            funcs.push_str(&format!(
+                "(func (export {:?}) (param i32 i32) (result i64)
+                    (call $pack (i32.const {}) (i32.const {})))\n",
+                name, offset, json.len()));
+            offset += json.len();
+        }
+
+        format!(r#"
+            (module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                {}
+                (func $pack (param i32 i32) (result i64)
+                    (i64.or
+                        (i64.shl (i64.extend_i32_u (local.get 0)) (i64.const 32))
+                        (i64.extend_i32_u (local.get 1))))
+                (func (export "alloc") (param i32) (result i32)
+                    (global.get $next)
+                    (global.set $next (i32.add (global.get $next) (local.get 0))))
+                {}
+                ;; the input has a state in it, so this is a no-op
+                (func $echo (param i32 i32) (result i64)
+                    (call $pack (local.get 0) (local.get 1)))
+                (export "create" (func $echo))
+                (export "state" (func $echo))
+                (export "action" (func $echo))
+                (func (export "timeout_action") (param i32 i32) (result i64)
+                    (loop $forever (br $forever))
+                    (i64.const 0))
+                (func (export "current_player") (param i32 i32) (result i64)
+                    unreachable)
+                (func (export "legal_actions") (param i32 i32) (result i64)
+                    (call $pack (i32.const 65536) (i32.const 16)))
+            )
+        "#, data, funcs)
+    }
+
+    fn load(wat: &str) -> Result<WasmGameFactory, Error> {
+        WasmGameFactory::new("test", &wat::parse_str(wat).unwrap())
+    }
+
+    #[test]
+    fn wasm_game_plays() {
+        let factory = load(&echo_game()).unwrap();
+        assert_eq!(factory.id(), "echo");
+        assert_eq!(factory.name(), "Echo");
+        assert_eq!(factory.max_players(), 2);
+        assert_eq!(factory.options_schema(), options::no_options());
+
+        let mut game = factory.create(
+            vec![format!("a"), format!("b")],
+            42,
+            serde_json::json!({})
+        ).unwrap();
+        let state = game.save().unwrap();
+        assert_eq!(state["players"], serde_json::json!(["a", "b"]));
+        assert_eq!(state["seed"], 42);
+        assert_eq!(game.state_for(Some("a")).0["player"], "a");
+        assert_eq!(game.status(), "playing");
+        assert!(!game.ended());
+
+        // actions go through the module, errors go back to the player
+        game.action(GameAction(serde_json::json!({"action": "poke"}))).unwrap();
+        assert_eq!(game.save().unwrap(), state);
+        assert_eq!(
+            game.player_left("a"),
+            Err(Error::InvalidAction("stay put".to_string()))
+        );
+
+        // and it survives a save
+        let game = factory.load(game.save().unwrap()).unwrap();
+        assert_eq!(game.save().unwrap(), state);
+    }
+
+    #[test]
+    fn wasm_reads_are_cached() {
+        let factory = load(&echo_game()).unwrap();
+        let mut game = WasmGame::new(
+            factory.module.clone(),
+            serde_json::json!({"turn": 1})
+        );
+
+        game.state_for(Some("a"));
+        game.state_for(Some("a"));
+        game.state_for(None);
+        game.status();
+        // failures too
+        assert!(game.timeout_action().is_none());
+        assert!(game.timeout_action().is_none());
+        assert_eq!(game.reads.borrow().len(), 4);
+        assert_eq!(game.state_for(None).0["player"], serde_json::Value::Null);
+
+        // until the state changes
+        game.action(GameAction(serde_json::json!({"action": "poke"}))).unwrap();
+        assert_eq!(game.reads.borrow().len(), 0);
+        assert_eq!(game.state_for(Some("b")).0["player"], "b");
+    }
+
+    #[test]
+    fn wasm_game_sandboxed() {
+        let factory = load(&echo_game()).unwrap();
+        let game = factory.create(vec![format!("a")], 0, serde_json::json!({}))
+            .unwrap();
+
+        // runs out of fuel
+        assert!(matches!(
+            factory.module.call("timeout_action", &serde_json::json!({}), UPDATE_FUEL),
+            Err(Error::Internal(_))
+        ));
+        assert!(game.timeout_action().is_none());
+        // traps
+        assert!(game.current_player().is_none());
+        // points outside its memory
+        assert!(game.legal_actions("a").is_none());
+
+        // nothing gets in
+        assert!(load(r#"
+            (module
+                (import "env" "escape" (func))
+                (memory (export "memory") 1)
+                (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                (func $f (param i32 i32) (result i64) (i64.const 0))
+                (export "info" (func $f))
+                (export "create" (func $f))
+                (export "state" (func $f))
+                (export "action" (func $f))
+                (export "status" (func $f))
+                (export "ended" (func $f))
+            )
+        "#).is_err());
+
+        // and modules need to be games
+        assert!(load(r#"(module (memory (export "memory") 1))"#).is_err());
+    }
+}